@@ -1,17 +1,36 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::config::{Seconds, MIN_POLL_INTERVAL};
 
 fn path_only_filename(p: &str) -> Result<PathBuf, String> {
     let filename = p.parse::<PathBuf>().map_err(|_| "not a valid filename")?;
     if let Some(parent) = filename.parent() {
         if parent != PathBuf::new() {
-            return Err("not a valid filename")?;
+            Err("not a valid filename")?;
         }
     }
     Ok(filename)
 }
 
+fn seconds(s: &str) -> Result<Seconds, String> {
+    s.parse::<f64>()
+        .map_err(|_| "not a valid number")?
+        .try_into()
+}
+
+fn poll_interval(s: &str) -> Result<Seconds, String> {
+    let interval = seconds(s)?;
+    if Duration::from(interval) < MIN_POLL_INTERVAL {
+        return Err(format!(
+            "must be at least {}",
+            f64::from(Seconds::from(MIN_POLL_INTERVAL))
+        ));
+    }
+    Ok(interval)
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Show the loaded configuration
@@ -22,25 +41,195 @@ pub enum Command {
     },
     /// Run a configuration
     #[command()]
-    Run {
-        /// Name of target to run
-        #[arg(name = "target name")]
-        target_name: Option<String>,
-        /// Watch provided paths and re-launch command on change
+    Run(RunArgs),
+    /// Show recent runs of this configuration
+    History {
+        /// Number of runs to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Only show runs of the target, including as a hook
         #[arg(short, long)]
-        watch: bool,
-        /// Extra arguments to pass to app
-        #[arg(name = "args", last = true, allow_hyphen_values = true)]
-        extra_args: Vec<String>,
+        target: Option<String>,
+        /// Only show runs that failed
+        #[arg(long)]
+        failed: bool,
+        /// Show runs of every configuration
+        #[arg(short, long)]
+        all: bool,
     },
+    /// Repeat the last run of this configuration
+    Rerun,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct RunArgs {
+    /// Name of targets to run, each followed by its parameters
+    #[arg(name = "target name", allow_hyphen_values = true)]
+    pub targets: Vec<String>,
+    #[command(flatten)]
+    pub options: RunOptions,
+    /// Run the targets at the same time
+    #[arg(short = 'P', long)]
+    pub parallel: bool,
+    /// Show what would be run, without running anything
+    #[arg(short = 'n', long, conflicts_with = "watch")]
+    pub dry_run: bool,
+    /// Continue running targets after one fails
+    #[arg(short, long, conflicts_with = "parallel")]
+    pub keep_going: bool,
+    /// Exit status to give when a target is skipped by its checks
+    #[arg(long, value_name = "STATUS", default_value_t = exitcode::OK)]
+    pub skip_status: i32,
+    /// Fail when a target is skipped by its checks, the same as '--skip-status 1'
+    #[arg(long, conflicts_with = "skip_status")]
+    pub fail_if_skipped: bool,
+    /// Show how long each check, hook and program took, once finished
+    #[arg(long)]
+    pub timings: bool,
+    /// Write how long each check, hook and program took to a Chrome trace file
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<PathBuf>,
+    /// Write the progress of the run as events to stderr, one per line
+    #[arg(long, value_name = "FORMAT")]
+    pub events: Option<EventFormat>,
+    /// Write events to the given file descriptor instead of stderr (unix only)
+    #[arg(long, value_name = "FD", requires = "events")]
+    pub events_fd: Option<i32>,
+    /// Extra arguments to pass to app
+    #[arg(name = "args", last = true, allow_hyphen_values = true)]
+    pub extra_args: Vec<String>,
+}
+
+impl RunArgs {
+    /// Converts back into the arguments given to `run`
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = self.options.to_args();
+        if self.parallel {
+            args.push(String::from("--parallel"));
+        }
+        if self.dry_run {
+            args.push(String::from("--dry-run"));
+        }
+        if self.keep_going {
+            args.push(String::from("--keep-going"));
+        }
+        if self.skip_status != exitcode::OK {
+            args.push(format!("--skip-status={}", self.skip_status));
+        }
+        if self.fail_if_skipped {
+            args.push(String::from("--fail-if-skipped"));
+        }
+        if self.timings {
+            args.push(String::from("--timings"));
+        }
+        if let Some(trace) = &self.trace {
+            args.push(format!("--trace={}", trace.display()));
+        }
+        if let Some(events) = self.events {
+            args.push(format!(
+                "--events={}",
+                events.to_possible_value().unwrap().get_name()
+            ));
+        }
+        if let Some(events_fd) = self.events_fd {
+            args.push(format!("--events-fd={}", events_fd));
+        }
+        args.extend(self.targets.iter().cloned());
+        if !self.extra_args.is_empty() {
+            args.push(String::from("--"));
+            args.extend(self.extra_args.iter().cloned());
+        }
+        args
+    }
+
+    /// Parses the arguments given to `run`
+    pub fn try_from_args(args: &[String]) -> Result<Self, clap::Error> {
+        let mut full_args = vec![String::from("run-tool"), String::from("run")];
+        full_args.extend(args.iter().cloned());
+        match Args::try_parse_from(full_args)?.command {
+            Command::Run(run_args) => Ok(run_args),
+            _ => unreachable!("parsed as run"),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum EventFormat {
+    /// Newline-delimited JSON
+    Json,
+}
+
+/// Options for running a target, which are also given to targets run in another process
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct RunOptions {
+    /// Watch provided paths and re-launch command on change
+    #[arg(short, long)]
+    pub watch: bool,
+    /// Poll for changes when watching, at an interval in seconds (default 1)
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = poll_interval,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    pub poll: Option<Seconds>,
+    /// Stop programs taking longer than the given seconds, overriding any configured timeout
+    #[arg(long, value_name = "SECONDS", value_parser = seconds)]
+    pub timeout: Option<Seconds>,
+}
+
+impl RunOptions {
+    /// Converts the options back into arguments for `run`
+    pub fn to_args(self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.watch {
+            args.push(String::from("--watch"));
+        }
+        if let Some(poll) = self.poll {
+            args.push(format!("--poll={}", f64::from(poll)));
+        }
+        if let Some(timeout) = self.timeout {
+            args.push(format!("--timeout={}", f64::from(timeout)));
+        }
+        args
+    }
+
+    /// Only the options that apply to hooks, which are run once and not watched
+    pub fn for_hooks(self) -> Self {
+        Self {
+            timeout: self.timeout,
+            ..Default::default()
+        }
+    }
+}
+
+/// Separates the extra arguments given after `--` from the targets,
+/// needed since the targets take all remaining arguments
+pub fn split_extra_args(mut targets: Vec<String>) -> (Vec<String>, Vec<String>) {
+    match targets.iter().position(|arg| arg == "--") {
+        Some(i) => {
+            let extra_args = targets.split_off(i + 1);
+            targets.pop();
+            (targets, extra_args)
+        }
+        None => (targets, Vec::new()),
+    }
 }
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about)]
 pub struct Args {
-    /// Whether to log verbosely
-    #[arg(short = 'v', long = "verbose")]
-    pub verbose_logging: bool,
+    /// Whether to log verbosely, given twice to also trace
+    #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+    pub verbose_logging: u8,
+    /// Only log errors
+    #[arg(short = 'q', long = "quiet", conflicts_with = "verbose_logging")]
+    pub quiet_logging: bool,
+    /// Do not colour the output
+    #[arg(long = "no-color")]
+    pub no_color: bool,
     /// Use user's global configuration
     #[arg(short = 'g', long = "global")]
     pub use_global_config: bool,
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, num::NonZeroU32, path::PathBuf, time::Duration};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use crate::helpers::{self, EnvVars};
 
@@ -20,15 +20,236 @@ impl From<FileOrFiles> for Vec<PathBuf> {
     }
 }
 
+/// A duration given in seconds, allowing fractions e.g. `0.5`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Seconds(Duration);
+
+impl From<Duration> for Seconds {
+    fn from(value: Duration) -> Self {
+        Self(value)
+    }
+}
+
+impl TryFrom<f64> for Seconds {
+    type Error = String;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        Duration::try_from_secs_f64(value)
+            .map(Seconds)
+            .map_err(|_| format!("'{}' is not a valid number of seconds", value))
+    }
+}
+
+impl From<Seconds> for f64 {
+    fn from(value: Seconds) -> Self {
+        value.0.as_secs_f64()
+    }
+}
+
+impl From<Seconds> for Duration {
+    fn from(value: Seconds) -> Self {
+        value.0
+    }
+}
+
+/// Shells that can run a command string
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Shell {
+    Sh,
+    Bash,
+    Pwsh,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        match cfg!(windows) {
+            true => Self::Pwsh,
+            false => Self::Sh,
+        }
+    }
+}
+
+impl Shell {
+    /// Gets the program and arguments to run the script,
+    /// stopping at the first failing command
+    pub fn program_args(&self, script: &str) -> (String, Vec<String>) {
+        match self {
+            Self::Sh | Self::Bash => (
+                format!("{:?}", self).to_lowercase(),
+                vec!["-e".to_owned(), "-c".to_owned(), script.to_owned()],
+            ),
+            Self::Pwsh => (
+                "pwsh".to_owned(),
+                vec![
+                    "-NoProfile".to_owned(),
+                    "-NonInteractive".to_owned(),
+                    "-Command".to_owned(),
+                    format!("$ErrorActionPreference = 'Stop'\n{}", script),
+                ],
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expected a 'program', a 'script' with an 'interpreter' or a 'cmd' with a 'shell' of 'sh', 'bash' or 'pwsh'"
+)]
+pub enum ExecCommand {
+    Program {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    Script {
+        script: String,
+        interpreter: String,
+    },
+    Shell {
+        #[serde(alias = "script")]
+        cmd: String,
+        shell: Option<Shell>,
+    },
+}
+
+impl ExecCommand {
+    /// Gets the program and arguments to execute, using the default shell when not set.
+    /// For a script run by an interpreter the path of the written script must be appended
+    pub fn program_args(&self, default_shell: Shell) -> (String, Vec<String>) {
+        match self {
+            Self::Program { program, args } => (program.to_owned(), args.to_owned()),
+            Self::Script { interpreter, .. } => {
+                let mut parts = interpreter.split_whitespace().map(str::to_owned);
+                (parts.next().unwrap_or_default(), parts.collect())
+            }
+            Self::Shell { cmd, shell } => {
+                let shell = shell.unwrap_or(default_shell);
+                let (program, mut args) = shell.program_args(cmd);
+                // makes extra arguments available as positional parameters e.g. `$1`
+                if shell != Shell::Pwsh {
+                    args.push(program.clone());
+                }
+                (program, args)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ExecCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Program { program, args } => write!(f, "{} {}", program, args.join(" ")),
+            Self::Script { interpreter, .. } => write!(f, "{} <script>", interpreter),
+            Self::Shell { cmd, .. } => write!(f, "{}", cmd),
+        }
+    }
+}
+
+/// Named places a program's input or output can be connected to
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    /// The terminal, or whatever run-tool is connected to
+    Inherit,
+    /// Nothing, discarding any output
+    Null,
+}
+
+/// Where a program's input comes from or output goes to
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(
+    untagged,
+    expecting = "expected a redirect of 'inherit', 'null', a 'file' or a 'target'"
+)]
+pub enum Redirect {
+    Stream(Stream),
+    File {
+        file: String,
+        #[serde(default)]
+        append: bool,
+    },
+    Target {
+        target: String,
+    },
+}
+
+impl std::fmt::Display for Redirect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Stream(Stream::Inherit) => write!(f, "inherit"),
+            Self::Stream(Stream::Null) => write!(f, "null"),
+            Self::File {
+                file,
+                append: false,
+            } => write!(f, "file '{}'", file),
+            Self::File { file, append: true } => write!(f, "file '{}' (append)", file),
+            Self::Target { target } => write!(f, "target '{}'", target),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ExecConfig {
-    pub program: String,
-    #[serde(default)]
-    pub args: Vec<String>,
+    #[serde(flatten)]
+    pub command: ExecCommand,
     #[serde(default)]
     pub env: EnvVars,
     pub env_file: Option<FileOrFiles>,
     pub cwd: Option<String>,
+    pub timeout: Option<Seconds>,
+    #[serde(default, deserialize_with = "present_redirect")]
+    pub stdin: Option<Redirect>,
+    #[serde(default, deserialize_with = "present_redirect")]
+    pub stdout: Option<Redirect>,
+    #[serde(default, deserialize_with = "present_redirect")]
+    pub stderr: Option<Redirect>,
+}
+
+/// Keeps a redirect given as `null` without quotes as `Stream::Null`,
+/// which would otherwise be treated as not given
+fn present_redirect<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Redirect>, D::Error> {
+    Option::<Redirect>::deserialize(deserializer)
+        .map(|redirect| Some(redirect.unwrap_or(Redirect::Stream(Stream::Null))))
+}
+
+/// Keys that belong to `ExecConfig`, any of which means a target runs a program
+const EXEC_KEYS: [&str; 13] = [
+    "program",
+    "args",
+    "cmd",
+    "script",
+    "interpreter",
+    "shell",
+    "env",
+    "env_file",
+    "cwd",
+    "timeout",
+    "stdin",
+    "stdout",
+    "stderr",
+];
+
+/// Gives a target's program when any of its keys are given, failing when they are not valid,
+/// as a flattened `Option` would otherwise treat an invalid program as not given
+fn present_exec<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ExecConfig>, D::Error> {
+    let mapping = serde_yml::Mapping::deserialize(deserializer)?;
+    let exec: serde_yml::Mapping = mapping
+        .into_iter()
+        .filter(|(key, _)| key.as_str().is_some_and(|key| EXEC_KEYS.contains(&key)))
+        .collect();
+    if exec.is_empty() {
+        return Ok(None);
+    }
+    ExecConfig::deserialize(serde_yml::Value::Mapping(exec))
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
 impl ExecConfig {
@@ -43,11 +264,66 @@ impl ExecConfig {
     }
 }
 
+/// A single value, which may be given as any YAML scalar
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Scalar {
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+}
+
+impl std::fmt::Display for Scalar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Boolean(v) => write!(f, "{}", v),
+            Self::Integer(v) => write!(f, "{}", v),
+            Self::Number(v) => write!(f, "{}", v),
+            Self::String(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParamConfig {
+    pub name: String,
+    pub help: Option<String>,
+    #[serde(rename = "type", default)]
+    pub param_type: ParamType,
+    #[serde(default)]
+    pub choices: Vec<Scalar>,
+    pub default: Option<Scalar>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub positional: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StepConfig {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub exec: ExecConfig,
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "when", content = "fields")]
 pub enum TargetCheck {
     #[serde(rename = "exec_ok")]
-    ExecOk(ExecConfig),
+    ExecOk(Box<ExecConfig>),
     #[serde(rename = "path_exists")]
     PathExists { path: PathBuf },
     #[serde(rename = "path_is_file")]
@@ -64,17 +340,198 @@ pub struct TargetCheckConfig {
     pub invert: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+impl std::fmt::Display for TargetCheckConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let not = if self.invert { "not " } else { "" };
+        match &self.when {
+            TargetCheck::ExecOk(exec) => write!(f, "'{}' does {}succeed", exec.command, not),
+            TargetCheck::PathExists { path } => {
+                write!(f, "'{}' does {}exist", path.display(), not)
+            }
+            TargetCheck::PathIsFile { path } => write!(f, "'{}' is {}a file", path.display(), not),
+            TargetCheck::PathIsDir { path } => {
+                write!(f, "'{}' is {}a directory", path.display(), not)
+            }
+        }
+    }
+}
+
+/// Signals that can be sent to a running program
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Signal {
+    #[default]
+    Sighup,
+    Sigint,
+    /// Only used to force processes to stop, as it cannot be handled
+    #[serde(skip)]
+    Sigkill,
+    Sigquit,
+    Sigterm,
+    Sigusr1,
+    Sigusr2,
+}
+
+#[cfg(unix)]
+impl Signal {
+    pub fn as_raw(&self) -> i32 {
+        match self {
+            Self::Sighup => libc::SIGHUP,
+            Self::Sigint => libc::SIGINT,
+            Self::Sigkill => libc::SIGKILL,
+            Self::Sigquit => libc::SIGQUIT,
+            Self::Sigterm => libc::SIGTERM,
+            Self::Sigusr1 => libc::SIGUSR1,
+            Self::Sigusr2 => libc::SIGUSR2,
+        }
+    }
+}
+
+/// What to do with a running program when a watched path changes
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchChangeMode {
+    /// Kill the program and run again
+    #[default]
+    Restart,
+    /// Wait for the program to finish and then run again
+    Queue,
+    /// Send a signal to the program, leaving it running
+    Signal,
+}
+
+/// Shortest interval to poll for changes at, as each poll reads every watched path
+/// so shorter ones would keep the CPU and disk busy
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn default_watch_debounce() -> Seconds {
+    Seconds(Duration::from_secs(2))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WatchConfig {
     pub paths: Vec<PathBuf>,
+    #[serde(default = "default_watch_debounce")]
+    pub debounce: Seconds,
+    #[serde(default)]
+    pub on_change: WatchChangeMode,
+    #[serde(default)]
+    pub signal: Signal,
+    #[serde(default)]
+    pub rerun_on_success: bool,
+    pub poll: Option<Seconds>,
+    #[serde(default)]
+    pub clear: bool,
+    #[serde(default)]
+    pub banner: bool,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            paths: Default::default(),
+            debounce: default_watch_debounce(),
+            on_change: Default::default(),
+            signal: Default::default(),
+            rerun_on_success: false,
+            poll: None,
+            clear: false,
+            banner: false,
+        }
+    }
+}
+
+/// How to open a log file
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogMode {
+    /// Add to the end of an existing file
+    #[default]
+    Append,
+    /// Empty an existing file first
+    Truncate,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_log_max_files() -> usize {
+    5
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogConfig {
+    pub file: String,
+    #[serde(default)]
+    pub mode: LogMode,
+    /// Whether to also show the output on the terminal
+    #[serde(default = "default_true")]
+    pub tee: bool,
+    #[serde(default)]
+    pub timestamps: bool,
+    /// Size in bytes to rotate the file at
+    pub max_size: Option<u64>,
+    /// Number of rotated files to keep
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+fn default_retry_attempts() -> NonZeroU32 {
+    NonZeroU32::new(3).unwrap()
+}
+
+fn default_retry_delay() -> Seconds {
+    Seconds(Duration::from_secs(1))
+}
+
+fn default_retry_backoff() -> f64 {
+    2.0
+}
+
+/// How to retry a failing target
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts to make in total, including the first
+    #[serde(default = "default_retry_attempts")]
+    pub attempts: NonZeroU32,
+    #[serde(default = "default_retry_delay")]
+    pub delay: Seconds,
+    /// Multiplies the delay after each attempt
+    #[serde(default = "default_retry_backoff")]
+    pub backoff: f64,
+}
+
+/// How the exit status of a pipe is decided
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipeStatus {
+    /// The status of the last target
+    Last,
+    /// The status of the last target to fail, like `pipefail` in shells
+    #[default]
+    AnyFail,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TargetConfig {
     pub description: Option<String>,
-    #[serde(flatten)]
+    pub usage: Option<String>,
+    #[serde(default)]
+    pub params: Vec<ParamConfig>,
+    #[serde(flatten, deserialize_with = "present_exec")]
     pub exec: Option<ExecConfig>,
     #[serde(default)]
+    pub steps: Vec<StepConfig>,
+    #[serde(default)]
+    pub parallel: Vec<String>,
+    #[serde(default)]
+    pub pipe: Vec<String>,
+    #[serde(default)]
+    pub pipe_status: PipeStatus,
+    pub retry: Option<RetryConfig>,
+    pub log: Option<LogConfig>,
+    #[serde(default)]
     pub run_when: Vec<TargetCheckConfig>,
     #[serde(default)]
     pub before_hooks: Vec<String>,
@@ -86,6 +543,163 @@ pub struct TargetConfig {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub shell: Shell,
     #[serde(default)]
     pub targets: HashMap<String, TargetConfig>,
 }
+
+impl Config {
+    /// Checks for settings that cannot be used together, naming the target they are in
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names: Vec<_> = self.targets.keys().collect();
+        names.sort();
+        for name in names {
+            let target = &self.targets[name];
+            let runs: Vec<_> = [
+                ("a program", target.exec.is_some()),
+                ("steps", !target.steps.is_empty()),
+                ("parallel", !target.parallel.is_empty()),
+                ("pipe", !target.pipe.is_empty()),
+            ]
+            .into_iter()
+            .filter_map(|(what, set)| set.then_some(what))
+            .collect();
+            if runs.len() > 1 {
+                return Err(format!(
+                    "target '{}' can only have one of a program, steps, parallel or pipe, found {}",
+                    name,
+                    runs.join(" and ")
+                ));
+            }
+            validate_params(&target.params).map_err(|msg| format!("target '{}' {}", name, msg))?;
+            if let Some(poll) = target
+                .watch
+                .poll
+                .filter(|poll| Duration::from(*poll) < MIN_POLL_INTERVAL)
+            {
+                return Err(format!(
+                    "target '{}' polls for changes every {}s, which must be at least {}s",
+                    name,
+                    f64::from(poll),
+                    f64::from(Seconds(MIN_POLL_INTERVAL))
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the parameters can be parsed from the command line
+fn validate_params(params: &[ParamConfig]) -> Result<(), String> {
+    let mut optional_positional: Option<&str> = None;
+    for (i, param) in params.iter().enumerate() {
+        if param.name.is_empty() {
+            return Err(String::from("has a parameter without a name"));
+        }
+        // taken by the target's help
+        if param.name == "help" {
+            return Err(String::from("cannot have a parameter named 'help'"));
+        }
+        if params[..i].iter().any(|other| other.name == param.name) {
+            return Err(format!(
+                "has more than one parameter named '{}'",
+                param.name
+            ));
+        }
+        if !param.positional {
+            continue;
+        }
+        if param.param_type == ParamType::Boolean {
+            return Err(format!(
+                "has a positional boolean parameter '{}', which must be given by name",
+                param.name
+            ));
+        }
+        let required = param.required && param.default.is_none();
+        match optional_positional {
+            Some(optional) if required => {
+                return Err(format!(
+                    "has the required positional parameter '{}' after the optional '{}'",
+                    param.name, optional
+                ))
+            }
+            None if !required => optional_positional = Some(&param.name),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(params: &str) -> Result<(), String> {
+        let config: Config = serde_yml::from_str(&format!(
+            "targets:\n  t:\n    program: 'true'\n    params: {}\n",
+            params
+        ))
+        .unwrap();
+        config.validate()
+    }
+
+    fn parse_target(fields: &str) -> Result<Config, serde_yml::Error> {
+        serde_yml::from_str(&format!("targets:\n  t:\n    {}\n", fields))
+    }
+
+    #[test]
+    fn rejects_invalid_program_instead_of_ignoring_it() {
+        assert!(parse_target("{cmd: 'true', timeout: -1}").is_err());
+        assert!(
+            parse_target("{cmd: 'true', timeout: 2}").unwrap().targets["t"]
+                .exec
+                .is_some()
+        );
+        assert!(parse_target("{parallel: [a], descripton: typo}")
+            .unwrap()
+            .targets["t"]
+            .exec
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_redirect() {
+        let err = parse_target("{cmd: 'true', stdout: nul}").unwrap_err();
+        assert!(
+            err.to_string().contains("expected a redirect of"),
+            "{}",
+            err
+        );
+        assert!(parse_target("{cmd: 'true', stdout: null}").is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_shell() {
+        let err = parse_target("{cmd: 'true', shell: zsh}").unwrap_err();
+        assert!(
+            err.to_string().contains("'sh', 'bash' or 'pwsh'"),
+            "{}",
+            err
+        );
+        assert!(parse_target("{cmd: 'true', shell: bash}").is_ok());
+    }
+
+    #[test]
+    fn accepts_valid_params() {
+        assert!(validate("[{name: a, positional: true, required: true}, {name: b, positional: true}, {name: c, type: boolean}]").is_ok());
+    }
+
+    #[test]
+    fn rejects_params_clap_cannot_parse() {
+        for params in [
+            "[{name: help}]",
+            "[{name: a}, {name: a}]",
+            "[{name: a, type: boolean, positional: true}]",
+            "[{name: a, positional: true}, {name: b, positional: true, required: true}]",
+        ] {
+            let err = validate(params).unwrap_err();
+            assert!(err.starts_with("target 't' "), "{}", err);
+        }
+    }
+}
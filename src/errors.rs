@@ -1,18 +1,260 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
 use exitcode::ExitCode;
 
+use crate::helpers::{programs_on_path, similar_names};
+
+/// What went wrong
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Described only by its message
+    Message(String),
+    /// The command line was not valid, already explaining what to change
+    Usage(String),
+    /// A target was asked for that is not in the configuration
+    UnknownTarget(String),
+    /// A file or directory could not be used
+    File {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A process could not be started or managed
+    Process {
+        action: &'static str,
+        program: String,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message(msg) | Self::Usage(msg) => write!(f, "{}", msg),
+            Self::UnknownTarget(name) => write!(f, "target '{}' not found", name),
+            Self::File {
+                action,
+                path,
+                source,
+            } => write!(f, "failed to {} '{}': '{}'", action, path.display(), source),
+            Self::Process {
+                program, source, ..
+            } if source.kind() == io::ErrorKind::NotFound => {
+                write!(f, "program '{}' not found", program)
+            }
+            Self::Process {
+                action,
+                program,
+                source,
+            } => write!(f, "failed to {} '{}': '{}'", action, program, source),
+        }
+    }
+}
+
+/// Where an error happened
+#[derive(Debug)]
+pub enum ErrorContext {
+    Target(String),
+    Hook(String),
+    Step(String),
+    Check(String),
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Target(name) => write!(f, "in target '{}'", name),
+            Self::Hook(name) => write!(f, "in hook '{}'", name),
+            Self::Step(name) => write!(f, "in step '{}'", name),
+            Self::Check(check) => write!(f, "in check {}", check),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AppError {
-    pub msg: String,
+    pub kind: ErrorKind,
     pub exitcode: ExitCode,
+    /// Where the error happened, innermost first
+    pub context: Vec<ErrorContext>,
+    /// A suggestion of how to fix the error
+    pub hint: Option<String>,
 }
 
 impl AppError {
+    pub fn new(msg: impl Into<String>, exitcode: ExitCode) -> Self {
+        Self::from_kind(ErrorKind::Message(msg.into()), exitcode)
+    }
+
+    pub fn from_kind(kind: ErrorKind, exitcode: ExitCode) -> Self {
+        Self {
+            kind,
+            exitcode,
+            context: Vec::new(),
+            hint: None,
+        }
+    }
+
+    /// A target is not in the configuration, suggesting the targets it could be
+    pub fn unknown_target<'a>(name: &str, targets: impl Iterator<Item = &'a String>) -> Self {
+        let targets: Vec<&str> = targets.map(String::as_str).collect();
+        let mut starting_with: Vec<_> = targets
+            .iter()
+            .copied()
+            .filter(|target| !name.is_empty() && target.starts_with(name))
+            .collect();
+        starting_with.sort();
+        let quoted = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| format!("'{}'", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let similar = similar_names(name, targets.into_iter());
+        let hint = if starting_with.len() > 1 {
+            format!("'{}' could be any of {}", name, quoted(&starting_with))
+        } else if similar.is_empty() {
+            String::from("use 'run-tool config -m' to see the available targets")
+        } else {
+            // only a few, as the rest are less likely
+            format!("did you mean {}?", quoted(&similar[..similar.len().min(3)]))
+        };
+        Self::from_kind(ErrorKind::UnknownTarget(name.to_owned()), exitcode::USAGE).with_hint(hint)
+    }
+
+    pub fn usage(msg: impl Into<String>) -> Self {
+        Self::from_kind(ErrorKind::Usage(msg.into()), exitcode::USAGE)
+    }
+
+    pub fn file(action: &'static str, path: &Path, source: io::Error, exitcode: ExitCode) -> Self {
+        Self::from_kind(
+            ErrorKind::File {
+                action,
+                path: path.to_owned(),
+                source,
+            },
+            exitcode,
+        )
+    }
+
+    pub fn process(action: &'static str, program: &str, source: io::Error) -> Self {
+        Self::from_kind(
+            ErrorKind::Process {
+                action,
+                program: program.to_owned(),
+                source,
+            },
+            exitcode::OSERR,
+        )
+    }
+
+    /// A program could not be started, suggesting why
+    pub fn spawn(program: &str, cwd: Option<&str>, source: io::Error) -> Self {
+        if let Some(cwd) = cwd.filter(|cwd| !Path::new(cwd).is_dir()) {
+            let source = match fs::metadata(cwd) {
+                Err(err) => err,
+                Ok(_) => io::Error::other("not a directory"),
+            };
+            return Self::file(
+                "use working directory",
+                Path::new(cwd),
+                source,
+                exitcode::NOINPUT,
+            )
+            .with_hint("check that 'cwd' is relative to the configuration file");
+        }
+        let hint = match source.kind() {
+            io::ErrorKind::NotFound if program.contains(std::path::is_separator) => Some(format!(
+                "check the path is correct, relative paths are from {}",
+                cwd.map(|cwd| format!("'{}'", cwd))
+                    .unwrap_or_else(|| String::from("the configuration file"))
+            )),
+            io::ErrorKind::NotFound => {
+                let programs = programs_on_path();
+                match similar_names(program, programs.iter().map(String::as_str)).first() {
+                    Some(similar) => Some(format!(
+                        "'{}' is not on PATH, did you mean '{}'?",
+                        program, similar
+                    )),
+                    None => Some(format!("check that '{}' is installed and on PATH", program)),
+                }
+            }
+            io::ErrorKind::PermissionDenied => {
+                Some(format!("check that '{}' is executable", program))
+            }
+            _ => None,
+        };
+        let mut err = Self::from_kind(
+            ErrorKind::Process {
+                action: "start",
+                program: program.to_owned(),
+                source,
+            },
+            exitcode::SOFTWARE,
+        );
+        err.hint = hint;
+        err
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Adds where the error happened, unless it is about the command line
+    pub fn context(mut self, context: ErrorContext) -> Self {
+        if !matches!(self.kind, ErrorKind::Usage(_) | ErrorKind::UnknownTarget(_)) {
+            self.context.push(context);
+        }
+        self
+    }
+
     pub fn handle(&self) -> ! {
-        eprintln!("{}", self.msg);
+        // errors are shown even when quiet, only turning logging off hides them
+        if log::max_level() != log::LevelFilter::Off {
+            eprintln!("{}", self);
+        }
         exit(self.exitcode);
     }
 }
 
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for context in &self.context {
+            write!(f, "\n  {}", context)?;
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\nhint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AppError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            ErrorKind::File { source, .. } | ErrorKind::Process { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Adds where an error happened to a result
+pub trait ResultExt {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Self;
+}
+
+impl<T> ResultExt for AppErrorResult<T> {
+    fn context(self, context: impl FnOnce() -> ErrorContext) -> Self {
+        self.map_err(|err| err.context(context()))
+    }
+}
+
 pub type AppErrorResult<T> = Result<T, AppError>;
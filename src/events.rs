@@ -0,0 +1,164 @@
+use std::{
+    env,
+    io::Write,
+    path::PathBuf,
+    process::Command,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Local};
+use exitcode::ExitCode;
+use serde::Serialize;
+
+use crate::args::EventFormat;
+use crate::config::Seconds;
+use crate::errors::{AppError, AppErrorResult};
+
+/// Set for run-tool processes started by run-tool, to the file descriptor events are written to
+const EVENTS_FD_ENV_VAR: &str = "RUN_TOOL_EVENTS_FD";
+
+static OUTPUT: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+/// The file descriptor events are written to, given to run-tool processes started by this one
+#[cfg(unix)]
+static OUTPUT_FD: OnceLock<i32> = OnceLock::new();
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum HookWhen {
+    Before,
+    After,
+}
+
+/// Progress of a run, for other programs to follow
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    TargetStarted {
+        target: &'a str,
+        args: &'a [String],
+    },
+    CheckEvaluated {
+        target: &'a str,
+        check: String,
+        passed: bool,
+    },
+    TargetSkipped {
+        target: &'a str,
+    },
+    HookStarted {
+        target: &'a str,
+        hook: &'a str,
+        when: HookWhen,
+    },
+    ProcessExited {
+        target: &'a str,
+        step: Option<&'a str>,
+        program: &'a str,
+        status: ExitCode,
+        duration: Seconds,
+    },
+    WatchTriggered {
+        target: &'a str,
+        paths: &'a [PathBuf],
+    },
+    TargetFinished {
+        target: &'a str,
+        status: ExitCode,
+    },
+}
+
+#[derive(Serialize)]
+struct EventLine<'a> {
+    time: DateTime<Local>,
+    pid: u32,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// Starts writing events to stderr or the given file descriptor,
+/// otherwise to the one given by run-tool when started by it
+pub fn init(format: Option<EventFormat>, fd: Option<i32>) -> AppErrorResult<()> {
+    if format.is_none() {
+        #[cfg(unix)]
+        if let Some(fd) = env::var(EVENTS_FD_ENV_VAR)
+            .ok()
+            .and_then(|fd| fd.parse::<i32>().ok())
+        {
+            // only for this process, the programs it runs must not hold it open
+            env::remove_var(EVENTS_FD_ENV_VAR);
+            // SAFETY: fcntl only changes the flags of the descriptor, failing when it is not open
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != -1 {
+                set_output_fd(fd);
+            }
+        }
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        // duplicated so it is not closed whilst in use, and is not inherited by the programs run
+        let fd = fd.unwrap_or(libc::STDERR_FILENO);
+        // SAFETY: fcntl only creates a new descriptor, failing when `fd` is not open
+        let output_fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if output_fd == -1 {
+            return Err(AppError::usage(format!(
+                "file descriptor {} for events is not open",
+                fd
+            )));
+        }
+        set_output_fd(output_fd);
+    }
+    #[cfg(not(unix))]
+    {
+        if fd.is_some() {
+            return Err(AppError::usage("'--events-fd' is only supported on unix"));
+        }
+        let _ = OUTPUT.set(Mutex::new(Box::new(std::io::stderr())));
+    }
+    Ok(())
+}
+
+/// Writes events to the file descriptor, which is owned from now on
+#[cfg(unix)]
+fn set_output_fd(fd: i32) {
+    use std::{fs::File, os::fd::FromRawFd};
+    if OUTPUT_FD.set(fd).is_ok() {
+        // SAFETY: the descriptor is open and nothing else in this process owns it
+        let _ = OUTPUT.set(Mutex::new(Box::new(unsafe { File::from_raw_fd(fd) })));
+    }
+}
+
+/// Lets a run-tool process started by this one write to the same events,
+/// as the file descriptor is otherwise closed for the programs run
+pub fn share_with(cmd: &mut Command) {
+    #[cfg(unix)]
+    if let Some(&fd) = OUTPUT_FD.get() {
+        use std::os::unix::process::CommandExt;
+        cmd.env(EVENTS_FD_ENV_VAR, fd.to_string());
+        // SAFETY: runs in the child before exec, only calling fcntl which is async-signal-safe
+        unsafe {
+            cmd.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+                -1 => Err(std::io::Error::last_os_error()),
+                _ => Ok(()),
+            });
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Writes the event as a line of JSON, when events are enabled
+pub fn emit(event: Event) {
+    let Some(output) = OUTPUT.get() else {
+        return;
+    };
+    let line = EventLine {
+        time: Local::now(),
+        pid: std::process::id(),
+        event,
+    };
+    if let Ok(mut line) = serde_json::to_vec(&line) {
+        line.push(b'\n');
+        // written at once, so lines from other processes are not mixed in
+        let _ = output.lock().unwrap().write_all(&line);
+    }
+}
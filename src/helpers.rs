@@ -2,11 +2,17 @@ use dotenvy::from_filename_iter;
 use std::collections::HashMap;
 use std::env;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::OnceLock;
 
 const CONFIG_FOLDER_NAME: &str = "run-tool";
+/// Also set for run-tool processes started by run-tool, so they log at the same level
+pub const LOG_ENV_VAR: &str = "RUN_TOOL_LOG";
+/// Also set for run-tool processes started by run-tool, so they do not colour their output either
+pub const NO_COLOR_ENV_VAR: &str = "RUN_TOOL_NO_COLOR";
 
 static APP_BIN_PATH: OnceLock<PathBuf> = OnceLock::new();
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
 
 pub type EnvVars = HashMap<String, String>;
 
@@ -14,6 +20,16 @@ pub fn get_app_binary_path() -> &'static PathBuf {
     APP_BIN_PATH.get_or_init(|| env::current_exe().expect("failed to get current binary path"))
 }
 
+/// Stops colouring output, including that of run-tool processes started by this one
+pub fn disable_color() {
+    NO_COLOR.store(true, Ordering::Relaxed);
+    colored::control::set_override(false);
+}
+
+pub fn color_disabled() -> bool {
+    NO_COLOR.load(Ordering::Relaxed)
+}
+
 pub fn get_config_file_names(custom_filename: Option<PathBuf>) -> Vec<PathBuf> {
     match (env::var("RUN_TOOL_FILENAME").ok(), custom_filename) {
         (None, None) => {
@@ -31,6 +47,19 @@ pub fn get_config_file_names(custom_filename: Option<PathBuf>) -> Vec<PathBuf> {
     }
 }
 
+/// Gets the log level from the arguments, otherwise the `RUN_TOOL_LOG` environment variable
+pub fn get_log_level(verbose: u8, quiet: bool) -> Result<log::LevelFilter, String> {
+    match (verbose, quiet, env::var(LOG_ENV_VAR).ok()) {
+        (0, true, _) => Ok(log::LevelFilter::Error),
+        (0, false, None) => Ok(log::LevelFilter::Info),
+        (0, false, Some(v)) => v
+            .parse()
+            .map_err(|_| format!("invalid log level '{}' in {}", v, LOG_ENV_VAR)),
+        (1, _, _) => Ok(log::LevelFilter::Debug),
+        (_, _, _) => Ok(log::LevelFilter::Trace),
+    }
+}
+
 pub fn get_app_config_path() -> Option<PathBuf> {
     if cfg!(windows) {
         match std::env::var("USERPROFILE") {
@@ -92,3 +121,92 @@ pub fn read_env_files(paths: &[PathBuf]) -> Result<EnvVars, String> {
     }
     Ok(variables)
 }
+
+/// Replaces each `{{ name }}` with the variable's value, leaving unknown names untouched
+pub fn interpolate(value: &str, vars: &EnvVars) -> String {
+    let mut interpolated = String::with_capacity(value.len());
+    let mut remaining = value;
+    while let Some(start) = remaining.find("{{") {
+        let Some(length) = remaining[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        let placeholder = &remaining[start..start + length];
+        interpolated.push_str(&remaining[..start]);
+        match vars.get(placeholder[2..placeholder.len() - 2].trim()) {
+            Some(v) => interpolated.push_str(v),
+            None => interpolated.push_str(placeholder),
+        }
+        remaining = &remaining[start + length..];
+    }
+    interpolated.push_str(remaining);
+    interpolated
+}
+
+/// Quotes an argument when it would otherwise be split or lost, for showing to the user
+pub fn quote_arg(arg: &str) -> String {
+    if arg.contains(['\n', '\r']) {
+        format!("{:?}", arg)
+    } else if arg.is_empty() || arg.contains(char::is_whitespace) {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    } else {
+        arg.to_owned()
+    }
+}
+
+/// Names of the programs in the directories of the `PATH` environment variable
+pub fn programs_on_path() -> Vec<String> {
+    let Some(paths) = env::var_os("PATH") else {
+        return Vec::new();
+    };
+    env::split_paths(&paths)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// Names similar enough to the given one to be a typo of it, closest first
+pub fn similar_names<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    // allows roughly one typo per three characters
+    let max_distance = (name.chars().count() / 3).max(1);
+    let mut similar: Vec<_> = names
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (strsim::damerau_levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    similar.sort();
+    similar.dedup();
+    similar
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> EnvVars {
+        EnvVars::from([
+            (String::from("env"), String::from("prod")),
+            (String::from("empty"), String::new()),
+        ])
+    }
+
+    #[test]
+    fn interpolates_known_names() {
+        assert_eq!(interpolate("deploy {{env}}", &vars()), "deploy prod");
+        assert_eq!(interpolate("{{ env }}-{{ env }}", &vars()), "prod-prod");
+        assert_eq!(interpolate("a{{ empty }}b", &vars()), "ab");
+    }
+
+    #[test]
+    fn leaves_unknown_names_and_unclosed_braces() {
+        assert_eq!(
+            interpolate("{{ nope }} {{ env }}", &vars()),
+            "{{ nope }} prod"
+        );
+        assert_eq!(interpolate("{{ env", &vars()), "{{ env");
+        assert_eq!(interpolate("no params", &vars()), "no params");
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::Instant,
+};
+
+use chrono::{DateTime, Local};
+use exitcode::ExitCode;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Seconds;
+use crate::errors::{AppError, AppErrorResult};
+use crate::helpers::get_app_config_path;
+
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+/// Runs to keep in the history file, removing the oldest ones
+const MAX_RECORDS: usize = 1000;
+/// Set for run-tool processes started by run-tool, so only the run the user asked for is recorded
+const NESTED_ENV_VAR: &str = "RUN_TOOL_NESTED";
+
+static CURRENT: Mutex<Option<(Instant, RunRecord)>> = Mutex::new(None);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TargetRecord {
+    pub name: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Hooks that were run, in order
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    /// Whether it did not run due to its `run_when` checks
+    #[serde(default)]
+    pub skipped: bool,
+    /// Not known for targets run in another process
+    pub status: Option<ExitCode>,
+    pub duration: Option<Seconds>,
+}
+
+/// A single invocation of `run`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunRecord {
+    /// Path of the configuration file used
+    pub config: PathBuf,
+    /// Arguments given to `run`
+    pub args: Vec<String>,
+    pub started: DateTime<Local>,
+    pub duration: Seconds,
+    pub status: ExitCode,
+    pub targets: Vec<TargetRecord>,
+}
+
+impl RunRecord {
+    /// Whether every target was skipped
+    pub fn skipped(&self) -> bool {
+        !self.targets.is_empty() && self.targets.iter().all(|t| t.skipped)
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    get_app_config_path().map(|p| p.join(HISTORY_FILE_NAME))
+}
+
+/// Starts recording a run, unless this process was started by run-tool
+pub fn begin(config: &Path, args: Vec<String>) {
+    if env::var_os(NESTED_ENV_VAR).is_some() {
+        // only for this process, the programs it runs are not run-tool
+        env::remove_var(NESTED_ENV_VAR);
+        return;
+    }
+    *CURRENT.lock().unwrap() = Some((
+        Instant::now(),
+        RunRecord {
+            config: config.to_owned(),
+            args,
+            started: Local::now(),
+            duration: Default::default(),
+            status: exitcode::OK,
+            targets: Vec::new(),
+        },
+    ));
+}
+
+/// Marks a run-tool process started by this one as part of the run, so it is not recorded
+pub fn share_with(cmd: &mut Command) {
+    cmd.env(NESTED_ENV_VAR, "1");
+}
+
+pub fn begin_target(name: &str, args: &[String]) {
+    if let Some((_, record)) = CURRENT.lock().unwrap().as_mut() {
+        record.targets.push(TargetRecord {
+            name: name.to_owned(),
+            args: args.to_owned(),
+            hooks: Vec::new(),
+            skipped: false,
+            status: None,
+            duration: None,
+        });
+    }
+}
+
+pub fn hook_ran(name: &str) {
+    if let Some(target) = CURRENT
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|(_, record)| record.targets.last_mut())
+    {
+        target.hooks.push(name.to_owned());
+    }
+}
+
+/// Records how the last target began ended
+pub fn end_target(status: ExitCode, skipped: bool, started: Instant) {
+    if let Some(target) = CURRENT
+        .lock()
+        .unwrap()
+        .as_mut()
+        .and_then(|(_, record)| record.targets.last_mut())
+    {
+        target.skipped = skipped;
+        target.status = Some(status);
+        target.duration = Some(started.elapsed().into());
+    }
+}
+
+/// Writes the run being recorded to the history file
+pub fn finish(status: ExitCode) {
+    let Some((started, mut record)) = CURRENT.lock().unwrap().take() else {
+        return;
+    };
+    record.status = status;
+    record.duration = started.elapsed().into();
+    if let Err(err) = write(record) {
+        log::warn!("failed to record run in history: {}", err);
+    }
+}
+
+fn write(record: RunRecord) -> AppErrorResult<()> {
+    let path = history_path()
+        .ok_or_else(|| AppError::new("could not locate user home directory", exitcode::NOINPUT))?;
+    let to_error = |err: std::io::Error| AppError::file("write", &path, err, exitcode::IOERR);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(to_error)?;
+    }
+    let line = serde_json::to_string(&record)
+        .map_err(|err| AppError::new(err.to_string(), exitcode::SOFTWARE))?;
+    let mut records = read_lines(&path);
+    if records.len() >= MAX_RECORDS {
+        records.drain(..=records.len() - MAX_RECORDS);
+        records.push(line);
+        fs::write(&path, records.join("\n") + "\n").map_err(to_error)
+    } else {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(to_error)?;
+        writeln!(file, "{}", line).map_err(to_error)
+    }
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Reads the recorded runs, oldest first, skipping any that cannot be understood
+pub fn read() -> Vec<RunRecord> {
+    let Some(path) = history_path() else {
+        return Vec::new();
+    };
+    read_lines(&path)
+        .iter()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
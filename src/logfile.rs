@@ -0,0 +1,168 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use crate::config::{LogConfig, LogMode};
+use crate::errors::{AppError, AppErrorResult};
+
+/// A log file shared between the threads writing to it
+pub type SharedLogFile = Arc<Mutex<LogFile>>;
+
+/// A file that output is written to line by line,
+/// being rotated once it reaches the maximum size
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    tee: bool,
+    timestamps: bool,
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+/// Gets the path a log file is moved to when rotated, e.g. `build.log.1`
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", n));
+    rotated.into()
+}
+
+impl LogFile {
+    pub fn open(config: &LogConfig, path: PathBuf) -> AppErrorResult<Self> {
+        let to_error =
+            |err: io::Error| AppError::file("open log file", &path, err, exitcode::CANTCREAT);
+        if let Some(parent) = path.parent().filter(|p| *p != Path::new("")) {
+            fs::create_dir_all(parent).map_err(to_error)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(config.mode == LogMode::Append)
+            .write(true)
+            .truncate(config.mode == LogMode::Truncate)
+            .open(&path)
+            .map_err(to_error)?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+        Ok(Self {
+            path,
+            file,
+            size,
+            tee: config.tee,
+            timestamps: config.timestamps,
+            max_size: config.max_size,
+            max_files: config.max_files,
+        })
+    }
+
+    pub fn shared(self) -> SharedLogFile {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Moves the current file to `<file>.1`, shifting older files along
+    /// and removing any beyond the number to keep
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+            // unless appending, writes would continue from the old end, leaving a gap
+            self.file.seek(SeekFrom::Start(0))?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, n);
+                if from.exists() {
+                    fs::rename(from, rotated_path(&self.path, n + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let timestamp = match self.timestamps {
+            true => chrono::Local::now()
+                .format("[%Y-%m-%d %H:%M:%S%.3f] ")
+                .to_string(),
+            false => String::new(),
+        };
+        let length = (timestamp.len() + line.len()) as u64;
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + length > max)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(timestamp.as_bytes())?;
+        self.file.write_all(line)?;
+        self.size += length;
+        Ok(())
+    }
+}
+
+/// Writes each line of output to the log file,
+/// also showing it on the terminal when the log is set to tee
+pub fn forward_to_log(
+    output: impl Read + Send + 'static,
+    log_file: SharedLogFile,
+    to_stderr: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let tee = log_file.lock().unwrap().tee;
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::new();
+        let mut failed = false;
+        while reader.read_until(b'\n', &mut line).unwrap_or_default() != 0 {
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            if tee {
+                let _ = match to_stderr {
+                    true => io::stderr().lock().write_all(&line),
+                    false => io::stdout().lock().write_all(&line),
+                };
+            }
+            if !failed {
+                if let Err(err) = log_file.lock().unwrap().write_line(&line) {
+                    log::warn!("failed to write to log file: '{}'", err);
+                    failed = true;
+                }
+            }
+            line.clear();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_lines(name: &str, config: &str) -> Vec<u8> {
+        let dir = std::env::temp_dir().join(format!("run-tool-test-{}", std::process::id()));
+        let path = dir.join(name);
+        let config: LogConfig = serde_yml::from_str(config).unwrap();
+        let mut log_file = LogFile::open(&config, path.clone()).unwrap();
+        for i in 0..10 {
+            log_file
+                .write_line(format!("line {}\n", i).as_bytes())
+                .unwrap();
+        }
+        let contents = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(dir);
+        contents
+    }
+
+    #[test]
+    fn rotating_without_keeping_files_starts_from_the_beginning() {
+        for mode in ["append", "truncate"] {
+            let contents = write_lines(
+                &format!("{}.log", mode),
+                &format!("{{file: x, mode: {}, max_size: 16, max_files: 0}}", mode),
+            );
+            assert_eq!(contents, b"line 8\nline 9\n", "mode {}", mode);
+        }
+    }
+}
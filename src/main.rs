@@ -1,29 +1,48 @@
 use std::{
+    collections::BTreeSet,
     env, fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use args::Args;
+use args::{Args, RunArgs, RunOptions};
 use clap::Parser;
-use config::{Config, TargetCheck, TargetCheckConfig};
+use config::{Config, Seconds, Shell, TargetCheck, TargetCheckConfig, WatchChangeMode};
 
 mod args;
 mod config;
 mod errors;
+mod events;
 mod helpers;
+mod history;
+mod logfile;
+mod parallel;
+mod params;
+mod pipeline;
+mod plan;
 mod runner;
+mod timings;
+mod watch;
 
-use errors::{AppError, AppErrorResult};
-use helpers::get_app_binary_path;
-use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
-use runner::ProcessRunner;
+use errors::{AppError, AppErrorResult, ErrorContext, ResultExt};
+use events::{Event, HookWhen};
+use exitcode::ExitCode;
+use helpers::{interpolate, EnvVars};
+use logfile::LogFile;
+use notify_debouncer_mini::{notify::RecursiveMode, DebounceEventResult};
+use parallel::run_parallel;
+use params::{exit_if_help, hint_misspelt_target, parse_params, split_targets};
+use pipeline::run_pipeline;
+use runner::{exit_if_interrupted, ProcessRunner};
+use watch::{
+    clear_screen, restart_banner, unsupported_filesystem, Stage, StageWatches, WatcherGuard,
+};
 
 // Gets the config, searching from current path.
 fn get_config(
@@ -37,51 +56,63 @@ fn get_config(
     };
 
     if let Some(found_path) = found_path {
-        if let Ok(contents) = fs::read_to_string(&found_path) {
-            if let Ok(v) = serde_yml::from_str(&contents) {
-                return Ok((found_path, v));
-            }
-            return Err(AppError {
-                msg: format!(
-                    "failed to parse configuration in '{}'",
-                    found_path.display()
+        let contents = fs::read_to_string(&found_path).map_err(|err| {
+            AppError::file("read configuration", &found_path, err, exitcode::IOERR)
+        })?;
+        let config: Config = serde_yml::from_str(&contents).map_err(|err| {
+            AppError::new(
+                format!(
+                    "failed to parse configuration in '{}': '{}'",
+                    found_path.display(),
+                    err
                 ),
-                exitcode: exitcode::CONFIG,
-            });
-        }
-        return Err(AppError {
-            msg: format!(
-                "could not read run configuration in '{}'",
-                found_path.display()
-            ),
-            exitcode: exitcode::IOERR,
-        });
+                exitcode::CONFIG,
+            )
+        })?;
+        config.validate().map_err(|msg| {
+            AppError::new(
+                format!(
+                    "invalid configuration in '{}': {}",
+                    found_path.display(),
+                    msg
+                ),
+                exitcode::CONFIG,
+            )
+        })?;
+        return Ok((found_path, config));
     }
-    Err(AppError {
-        msg: format!("failed to find config, searched in '{}'", base.display()),
-        exitcode: exitcode::NOINPUT,
-    })
+    Err(AppError::new(
+        format!("failed to find config, searched in '{}'", base.display()),
+        exitcode::NOINPUT,
+    ))
 }
 
 fn check_if_run_needed<'a>(
+    target_name: &str,
     checks: impl Iterator<Item = &'a TargetCheckConfig>,
+    default_shell: Shell,
+    params: &EnvVars,
+    timeout: Option<Seconds>,
 ) -> AppErrorResult<bool> {
-    let checks = checks.map(|check| match &check.when {
-        TargetCheck::ExecOk(fields) => Ok(exitcode::is_success(
-            ProcessRunner {
-                program: fields.program.clone(),
-                args: fields.args.clone(),
-                vars: fields.all_vars().map_err(|err| AppError {
-                    msg: format!("failed to parse environment files: '{}'", err),
-                    exitcode: exitcode::DATAERR,
-                })?,
-                cwd: fields.cwd.clone(),
-            }
-            .run_interactive()?,
-        ) != check.invert),
-        TargetCheck::PathExists { path } => Ok(path.exists() != check.invert),
-        TargetCheck::PathIsFile { path } => Ok(path.is_file() != check.invert),
-        TargetCheck::PathIsDir { path } => Ok(path.is_dir() != check.invert),
+    let checks = checks.map(|check| {
+        let name = check.to_string();
+        let passed = timings::record("check", &name, || match &check.when {
+            TargetCheck::ExecOk(fields) => Ok(exitcode::is_success(
+                ProcessRunner::from_exec(fields, default_shell, params, &[])?
+                    .with_timeout(timeout)
+                    .run_interactive()?,
+            ) != check.invert),
+            TargetCheck::PathExists { path } => Ok(path.exists() != check.invert),
+            TargetCheck::PathIsFile { path } => Ok(path.is_file() != check.invert),
+            TargetCheck::PathIsDir { path } => Ok(path.is_dir() != check.invert),
+        })
+        .context(|| ErrorContext::Check(name.clone()))?;
+        events::emit(Event::CheckEvaluated {
+            target: target_name,
+            check: name,
+            passed,
+        });
+        Ok(passed)
     });
     for ok in checks {
         if !ok? {
@@ -111,12 +142,26 @@ fn command_config(config_path: PathBuf, config: Config, minimal: bool) -> AppErr
         }
         println!("    exec:");
         if let Some(exec) = target.1.exec {
-            println!("      {} {}", exec.program, exec.args.join(" "));
+            println!("      {}", exec.command);
             if let Some(cwd) = exec.cwd {
                 println!("    cwd:");
                 println!("      {}", cwd);
             }
         }
+        if !target.1.steps.is_empty() {
+            println!("    steps:");
+            for step in &target.1.steps {
+                println!("      {}", step.exec.command);
+            }
+        }
+        if !target.1.parallel.is_empty() {
+            println!("    parallel:");
+            println!("      {}", target.1.parallel.join(", "));
+        }
+        if !target.1.pipe.is_empty() {
+            println!("    pipe:");
+            println!("      {}", target.1.pipe.join(" | "));
+        }
         if !target.1.before_hooks.is_empty() {
             println!("    before hooks:");
             println!("      {}", target.1.before_hooks.join(", "));
@@ -131,139 +176,774 @@ fn command_config(config_path: PathBuf, config: Config, minimal: bool) -> AppErr
     Ok(())
 }
 
+/// How a target ended, when it did not encounter an error
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    /// Ran with the exit status
+    Ran(ExitCode),
+    /// Not run, as one of its `run_when` checks failed
+    Skipped,
+}
+
+impl Outcome {
+    /// Exit status of the outcome, giving `skip_status` when skipped
+    fn status(self, skip_status: ExitCode) -> ExitCode {
+        match self {
+            Self::Ran(status) => status,
+            Self::Skipped => skip_status,
+        }
+    }
+}
+
+/// Same as `command_run()`, also recording the target in the history
+fn command_run_recorded(
+    config: &Config,
+    target_name: &str,
+    target_args: &[String],
+    extra_args: Vec<String>,
+    options: &RunOptions,
+) -> AppErrorResult<Outcome> {
+    let started = Instant::now();
+    history::begin_target(target_name, target_args);
+    events::emit(Event::TargetStarted {
+        target: target_name,
+        args: target_args,
+    });
+    let result = timings::record("target", target_name, || {
+        command_run(config, target_name, target_args, extra_args, options)
+    })
+    .context(|| ErrorContext::Target(target_name.to_owned()));
+    let status = match &result {
+        Ok(outcome) => outcome.status(exitcode::OK),
+        Err(err) => err.exitcode,
+    };
+    history::end_target(status, matches!(result, Ok(Outcome::Skipped)), started);
+    if !matches!(result, Ok(Outcome::Skipped)) {
+        events::emit(Event::TargetFinished {
+            target: target_name,
+            status,
+        });
+    }
+    result
+}
+
 fn command_run(
-    config: Config,
+    config: &Config,
     target_name: &str,
+    target_args: &[String],
     extra_args: Vec<String>,
-    watch: bool,
-) -> AppErrorResult<()> {
-    let target_config = config.targets.get(target_name).ok_or_else(|| AppError {
-        msg: "run configuration not found".to_owned(),
-        exitcode: exitcode::USAGE,
-    })?;
+    options: &RunOptions,
+) -> AppErrorResult<Outcome> {
+    let RunOptions {
+        watch,
+        poll,
+        timeout,
+    } = *options;
+    let target_config = config
+        .targets
+        .get(target_name)
+        .ok_or_else(|| AppError::unknown_target(target_name, config.targets.keys()))?;
+    let params = parse_params(target_name, target_config, config.shell, target_args)
+        .map_err(|err| hint_misspelt_target(config, target_args, err))?;
 
-    let environment_variables = match &target_config.exec {
-        Some(exec) => exec.all_vars().map_err(|err| AppError {
-            msg: format!("failed to parse environment files: '{}'", err),
-            exitcode: exitcode::DATAERR,
-        })?,
-        None => Default::default(),
+    // each step to execute, with a name when part of multiple steps
+    let mut exec_steps = match &target_config.exec {
+        Some(exec) => vec![(
+            None,
+            ProcessRunner::from_exec(exec, config.shell, &params, &extra_args)?
+                .with_timeout(timeout),
+            false,
+        )],
+        None => {
+            if !extra_args.is_empty() && !target_config.steps.is_empty() {
+                log::warn!("extra arguments are not given to steps");
+            }
+            target_config
+                .steps
+                .iter()
+                .map(|step| {
+                    Ok((
+                        Some(step.name.clone().unwrap_or(step.exec.command.to_string())),
+                        ProcessRunner::from_exec(&step.exec, config.shell, &params, &[])?
+                            .with_timeout(timeout),
+                        step.continue_on_error,
+                    ))
+                })
+                .collect::<AppErrorResult<Vec<_>>>()?
+        }
     };
 
-    if !check_if_run_needed(target_config.run_when.iter())? {
-        return Err(AppError {
-            msg: format!("skipping '{}'", target_name),
-            exitcode: exitcode::OK,
+    if !check_if_run_needed(
+        target_name,
+        target_config.run_when.iter(),
+        config.shell,
+        &params,
+        timeout,
+    )? {
+        events::emit(Event::TargetSkipped {
+            target: target_name,
         });
+        log::info!("skipping '{}'", target_name);
+        return Ok(Outcome::Skipped);
     }
 
-    let run_hook = |name: &str| -> AppErrorResult<()> {
-        let status = ProcessRunner {
-            program: get_app_binary_path().to_str().unwrap().to_owned(),
-            args: vec![String::from("run"), String::from(name)],
-            vars: Default::default(),
-            cwd: None,
+    // opened once the target is known to run, so a truncated log is only emptied then
+    if let Some(log_config) = &target_config.log {
+        let log_file = LogFile::open(
+            log_config,
+            PathBuf::from(interpolate(&log_config.file, &params)),
+        )?
+        .shared();
+        for (_, runner, _) in exec_steps.iter_mut() {
+            runner.log_file = Some(log_file.clone());
         }
-        .run_interactive()
-        .map_err(|err| AppError {
-            msg: format!("hook '{}' encountered an error: '{}'", name, err.msg),
-            exitcode: exitcode::SOFTWARE,
-        })?;
+    }
+
+    let hook_args = options.for_hooks().to_args();
+    let run_hook = |name: &str| -> AppErrorResult<()> {
+        history::hook_ran(name);
+        let mut args = vec![String::from("run")];
+        args.extend(hook_args.iter().cloned());
+        args.push(name.to_owned());
+        let status = ProcessRunner::nested(args)
+            .run_interactive()
+            .context(|| ErrorContext::Hook(name.to_owned()))?;
         if exitcode::is_error(status) {
-            Err(AppError {
-                msg: "stop hook run due to error".to_owned(),
-                exitcode: status,
-            })
+            Err(AppError::new(
+                format!("hook '{}' exited with status {}", name, status),
+                status,
+            ))
         } else {
             Ok(())
         }
     };
 
+    let stages = StageWatches::new(config, target_config);
+    let pending_stages = Arc::new(Mutex::new(stages.all()));
+    let changed_paths = Arc::new(Mutex::new(Vec::new()));
+    // before hooks to run again whilst the program keeps running, before it is signalled
+    let signal_stages = Arc::new(Mutex::new(BTreeSet::new()));
+
     // TODO maybe polling can be improved to instead use `std::sync::Condvar` or something else?
     // Since this is expensive!
-    let should_restart = Arc::new(AtomicBool::new(true));
     let should_cancel = Arc::new(AtomicBool::new(false));
+    let should_signal = Arc::new(AtomicBool::new(false));
+    let exec_running = Arc::new(AtomicBool::new(false));
+    let watch_failed = Arc::new(AtomicBool::new(false));
 
-    let mut debounced_watcher = new_debouncer(Duration::from_secs(2), {
-        let should_restart = should_restart.clone();
+    let watch_config = &target_config.watch;
+    #[cfg(not(unix))]
+    if watch && watch_config.on_change == WatchChangeMode::Signal {
+        return Err(AppError::new(
+            "watch 'on_change: signal' is only supported on unix",
+            exitcode::CONFIG,
+        ));
+    }
+
+    let poll_interval = poll.or(watch_config.poll).map(Into::into);
+    let mut debounced_watcher = WatcherGuard::new(watch_config.debounce.into(), poll_interval, {
+        let pending_stages = pending_stages.clone();
+        let changed_paths = changed_paths.clone();
         let should_cancel = should_cancel.clone();
-        move |res| {
-            if let Ok(_) = res {
-                should_restart.store(true, Ordering::Relaxed);
+        let should_signal = should_signal.clone();
+        let signal_stages = signal_stages.clone();
+        let exec_running = exec_running.clone();
+        let watch_failed = watch_failed.clone();
+        let on_change = watch_config.on_change;
+        let stages = stages.clone();
+        let target_name = target_name.to_owned();
+        move |res: DebounceEventResult| match res {
+            Ok(events) => {
+                let changed: Vec<PathBuf> = events.into_iter().map(|e| e.path).collect();
+                let affected = stages.affected(&changed);
+                if affected.is_empty() {
+                    return;
+                }
+                log::debug!("changes detected in {:?}, running {:?}", changed, affected);
+                events::emit(Event::WatchTriggered {
+                    target: &target_name,
+                    paths: &changed,
+                });
+                let affects_exec = affected.contains(&Stage::Exec);
+                if affects_exec
+                    && on_change == WatchChangeMode::Signal
+                    && exec_running.load(Ordering::Relaxed)
+                {
+                    signal_stages.lock().unwrap().extend(
+                        affected
+                            .into_iter()
+                            .filter(|stage| matches!(stage, Stage::BeforeHook(_))),
+                    );
+                    should_signal.store(true, Ordering::Relaxed);
+                    return;
+                }
+                changed_paths.lock().unwrap().extend(changed);
+                pending_stages.lock().unwrap().extend(affected);
+                // only a restart should interrupt the running program
+                if affects_exec && on_change == WatchChangeMode::Restart {
+                    should_cancel.store(true, Ordering::Relaxed);
+                }
             }
-            should_cancel.store(true, Ordering::Relaxed);
+            Err(_) => watch_failed.store(true, Ordering::Relaxed),
         }
     })
-    .map_err(|e| AppError {
-        msg: format!(
-            "an issue occurred while trying to construct the file watcher: '{:?}'",
-            e,
-        ),
-        exitcode: exitcode::SOFTWARE,
+    .map_err(|e| {
+        AppError::new(
+            format!(
+                "an issue occurred while trying to construct the file watcher: '{:?}'",
+                e,
+            ),
+            exitcode::SOFTWARE,
+        )
     })?;
     if watch {
-        for p in target_config.watch.paths.iter() {
+        for p in stages.paths() {
+            if let Some(fs_name) =
+                unsupported_filesystem(p).filter(|_| !debounced_watcher.is_polling())
+            {
+                log::warn!(
+                    "watch path '{}' is on a {} filesystem which may not report changes, consider polling",
+                    p.display(),
+                    fs_name
+                );
+            }
             debounced_watcher
                 .watcher()
                 .watch(p, RecursiveMode::Recursive)
-                .map_err(|e| AppError {
-                    msg: format!(
-                        "an issue occurred while trying to add a path to the watcher: '{:?}'",
-                        e
-                    ),
-                    exitcode: exitcode::SOFTWARE,
+                .map_err(|e| {
+                    AppError::new(
+                        format!(
+                            "an issue occurred while trying to add a path to the watcher: '{:?}'",
+                            e
+                        ),
+                        exitcode::SOFTWARE,
+                    )
                 })?;
         }
     }
 
-    loop {
-        while should_restart.load(Ordering::Relaxed) {
-            should_restart.store(false, Ordering::Relaxed);
-            should_cancel.store(false, Ordering::Relaxed);
-
-            for before in &target_config.before_hooks {
-                run_hook(before)?;
+    let run_before_hook = |i: usize| -> AppErrorResult<()> {
+        let name = &target_config.before_hooks[i];
+        events::emit(Event::HookStarted {
+            target: target_name,
+            hook: name,
+            when: HookWhen::Before,
+        });
+        timings::record("before hook", name, || run_hook(name))
+    };
+    // the program is only signalled once the hooks it depends on have succeeded
+    #[cfg(unix)]
+    let run_signal_hooks = || -> bool {
+        let stages = std::mem::take(&mut *signal_stages.lock().unwrap());
+        for stage in stages {
+            if let Stage::BeforeHook(i) = stage {
+                if let Err(err) = run_before_hook(i) {
+                    log::error!("{}", err);
+                    log::info!("waiting for changes");
+                    return false;
+                }
             }
+        }
+        true
+    };
 
-            if let Some(exec) = &target_config.exec {
-                let mut args = exec.args.clone();
-                args.extend(extra_args.clone());
+    let run_stage = |stage: Stage| -> AppErrorResult<ExitCode> {
+        match stage {
+            Stage::BeforeHook(i) => {
+                return run_before_hook(i).map(|_| exitcode::OK);
+            }
+            Stage::AfterHook(i) => {
+                let name = &target_config.after_hooks[i];
+                events::emit(Event::HookStarted {
+                    target: target_name,
+                    hook: name,
+                    when: HookWhen::After,
+                });
+                return timings::record("after hook", name, || run_hook(name))
+                    .map(|_| exitcode::OK);
+            }
+            Stage::Exec if exec_steps.is_empty() && !target_config.pipe.is_empty() => {
+                should_cancel.store(false, Ordering::Relaxed);
+                let started = Instant::now();
+                let pipe = target_config.pipe.join(" | ");
+                let status = timings::record("pipe", &pipe, || {
+                    run_pipeline(
+                        &target_config.pipe,
+                        &hook_args,
+                        target_config.pipe_status,
+                        &should_cancel,
+                    )
+                })?;
+                events::emit(Event::ProcessExited {
+                    target: target_name,
+                    step: None,
+                    program: &pipe,
+                    status,
+                    duration: started.elapsed().into(),
+                });
+                return Ok(status);
+            }
+            Stage::Exec if exec_steps.is_empty() && !target_config.parallel.is_empty() => {
+                should_cancel.store(false, Ordering::Relaxed);
+                let targets: Vec<_> = target_config
+                    .parallel
+                    .iter()
+                    .map(|name| (name.to_owned(), Vec::new()))
+                    .collect();
+                let started = Instant::now();
+                let parallel = target_config.parallel.join(", ");
+                let status = timings::record("parallel", &parallel, || {
+                    run_parallel(&targets, &hook_args, &should_cancel)
+                })?;
+                events::emit(Event::ProcessExited {
+                    target: target_name,
+                    step: None,
+                    program: &parallel,
+                    status,
+                    duration: started.elapsed().into(),
+                });
+                return Ok(status);
+            }
+            Stage::Exec if exec_steps.is_empty() => {
+                log::info!("no program specified in target '{target_name}', skipping");
+                return Ok(exitcode::OK);
+            }
+            Stage::Exec => (),
+        };
 
-                let status = ProcessRunner {
-                    program: exec.program.clone(),
-                    args,
-                    vars: environment_variables.clone(),
-                    cwd: exec.cwd.clone(),
+        should_cancel.store(false, Ordering::Relaxed);
+        should_signal.store(false, Ordering::Relaxed);
+        for (i, (step_name, runner, continue_on_error)) in exec_steps.iter().enumerate() {
+            if let Some(step_name) = step_name {
+                log::info!(
+                    "running step '{}' ({}/{})",
+                    step_name,
+                    i + 1,
+                    exec_steps.len()
+                );
+            }
+            exec_running.store(true, Ordering::Relaxed);
+            let started = Instant::now();
+            let (part, name) = match step_name {
+                Some(step_name) => ("step", step_name.as_str()),
+                None => ("exec", target_name),
+            };
+            let status = timings::record(part, name, || match (watch, watch_config.on_change) {
+                (false, _) | (true, WatchChangeMode::Queue) => runner.run_interactive(),
+                (true, WatchChangeMode::Restart) => {
+                    runner.run_interactive_cancelable(&should_cancel)
                 }
-                .run_interactive_cancelable(&should_cancel)
-                .unwrap_or_else(|err| err.handle());
-                if status != exitcode::OK {
-                    exit(status);
+                #[cfg(unix)]
+                (true, WatchChangeMode::Signal) => runner.run_interactive_signalable(
+                    &should_signal,
+                    watch_config.signal,
+                    run_signal_hooks,
+                ),
+                #[cfg(not(unix))]
+                (true, WatchChangeMode::Signal) => unreachable!("checked before watching"),
+            });
+            exec_running.store(false, Ordering::Relaxed);
+            let status = match step_name {
+                Some(step_name) => status.context(|| ErrorContext::Step(step_name.to_owned()))?,
+                None => status?,
+            };
+            events::emit(Event::ProcessExited {
+                target: target_name,
+                step: step_name.as_deref(),
+                program: &runner.program,
+                status,
+                duration: started.elapsed().into(),
+            });
+            // stopped to restart, so the remaining steps must not run
+            if should_cancel.load(Ordering::Relaxed) {
+                return Ok(exitcode::OK);
+            }
+            if status == exitcode::OK {
+                continue;
+            }
+            match (step_name, continue_on_error) {
+                (Some(step_name), true) => {
+                    log::warn!("step '{step_name}' exited with status {status}, continuing")
                 }
-            } else {
-                log::info!("no program specified in target '{target_name}', skipping");
+                (Some(step_name), false) => {
+                    log::error!("step '{step_name}' exited with status {status}");
+                    return Ok(status);
+                }
+                (None, _) => return Ok(status),
             }
+        }
+        Ok(exitcode::OK)
+    };
 
-            for after in &target_config.after_hooks {
-                run_hook(after)?;
+    // only the program is retried, as hooks are targets with their own retries
+    let run_stage_retrying = |stage: Stage| -> AppErrorResult<ExitCode> {
+        let Some(retry) = target_config
+            .retry
+            .as_ref()
+            .filter(|_| stage == Stage::Exec)
+        else {
+            return run_stage(stage);
+        };
+        let mut delay: Duration = retry.delay.into();
+        let mut attempt = 1;
+        loop {
+            let result = run_stage(stage);
+            let status = match &result {
+                Ok(status) => *status,
+                Err(err) => err.exitcode,
+            };
+            if status == exitcode::OK || should_cancel.load(Ordering::Relaxed) {
+                if attempt > 1 && status == exitcode::OK {
+                    log::info!("'{}' succeeded on attempt {}", target_name, attempt);
+                }
+                return result;
+            }
+            if attempt >= retry.attempts.get() {
+                log::error!(
+                    "'{}' failed after {} attempts with status {}",
+                    target_name,
+                    attempt,
+                    status
+                );
+                return result;
             }
+            log::warn!(
+                "attempt {}/{} of '{}' failed with status {}, retrying in {:.1}s",
+                attempt,
+                retry.attempts,
+                target_name,
+                status,
+                delay.as_secs_f64()
+            );
+            sleep(delay);
+            exit_if_interrupted();
+            // an invalid backoff keeps the same delay, rather than panicking
+            delay =
+                Duration::try_from_secs_f64(delay.as_secs_f64() * retry.backoff).unwrap_or(delay);
+            attempt += 1;
         }
-        if watch && should_cancel.load(Ordering::Relaxed) || !watch {
+    };
+
+    let next_stage = || pending_stages.lock().unwrap().pop_first();
+    let take_changed_paths = || std::mem::take(&mut *changed_paths.lock().unwrap());
+    // how the program last ended and how long it ran for, shown in the banner
+    let mut previous_run = None;
+
+    loop {
+        let mut completed = false;
+        // once caught, interrupts no longer exit whilst waiting for changes
+        exit_if_interrupted();
+        while let Some(stage) = next_stage() {
+            let changed = take_changed_paths();
+            if !changed.is_empty() {
+                if watch_config.clear {
+                    clear_screen();
+                }
+                if watch_config.banner {
+                    eprintln!("{}", restart_banner(target_name, &changed, previous_run));
+                }
+            }
+            let stage_started = Instant::now();
+            let result = run_stage_retrying(stage);
+            if stage == Stage::Exec {
+                let status = match &result {
+                    Ok(_) if should_cancel.load(Ordering::Relaxed) => None,
+                    Ok(status) => Some(*status),
+                    Err(err) => Some(err.exitcode),
+                };
+                previous_run = Some((status, stage_started.elapsed()));
+            }
+            let failure = match result {
+                Ok(exitcode::OK) => {
+                    completed = true;
+                    continue;
+                }
+                Ok(status) if !watch => return Ok(Outcome::Ran(status)),
+                Err(err) if !watch => return Err(err),
+                Ok(status) => format!("'{target_name}' exited with status {status}"),
+                Err(err) => err.to_string(),
+            };
+            log::error!("{}", failure);
+            log::info!("waiting for changes");
+            // stages after a failure depend on it, so they must wait for the next change
+            pending_stages.lock().unwrap().clear();
+            completed = false;
+        }
+        if !watch || watch_failed.load(Ordering::Relaxed) {
             break;
         }
+        if completed && watch_config.rerun_on_success {
+            sleep(watch_config.debounce.into());
+            pending_stages.lock().unwrap().extend(stages.all());
+        }
         sleep(Duration::from_millis(1));
     }
 
+    Ok(Outcome::Ran(exitcode::OK))
+}
+
+fn command_run_sequential(
+    config: Config,
+    targets: &[(String, Vec<String>)],
+    options: &RunOptions,
+    keep_going: bool,
+    skip_status: ExitCode,
+) -> AppErrorResult<ExitCode> {
+    let mut status = exitcode::OK;
+    let mut outcomes = Vec::new();
+    for (name, target_args) in targets {
+        if status != exitcode::OK && !keep_going {
+            outcomes.push((name, String::from("not run"), None));
+            continue;
+        }
+        let started = Instant::now();
+        let outcome = match command_run_recorded(&config, name, target_args, Vec::new(), options) {
+            Ok(Outcome::Ran(exitcode::OK)) => String::from("ok"),
+            Ok(Outcome::Skipped) if skip_status == exitcode::OK => String::from("skipped"),
+            Ok(Outcome::Skipped) => {
+                if status == exitcode::OK {
+                    status = skip_status;
+                }
+                format!("skipped ({})", skip_status)
+            }
+            Ok(Outcome::Ran(target_status)) => {
+                log::error!("'{}' exited with status {}", name, target_status);
+                if status == exitcode::OK {
+                    status = target_status;
+                }
+                format!("failed ({})", target_status)
+            }
+            Err(err) => {
+                log::error!("{}", err);
+                if status == exitcode::OK {
+                    status = err.exitcode;
+                }
+                format!("failed ({})", err.exitcode)
+            }
+        };
+        outcomes.push((name, outcome, Some(started.elapsed())));
+    }
+
+    if !log::log_enabled!(log::Level::Info) {
+        return Ok(status);
+    }
+    // columns must at least fit their headings
+    let name_width = targets.iter().map(|(n, _)| n.len()).fold(6, usize::max);
+    let outcome_width = outcomes.iter().map(|(_, o, _)| o.len()).fold(6, usize::max);
+    eprintln!(
+        "{:<name_width$}  {:<outcome_width$}  duration",
+        "target", "status"
+    );
+    for (name, outcome, duration) in outcomes {
+        let duration = duration
+            .map(|d| format!("{:.1}s", d.as_secs_f64()))
+            .unwrap_or_else(|| String::from("-"));
+        eprintln!(
+            "{:<name_width$}  {:<outcome_width$}  {}",
+            name, outcome, duration
+        );
+    }
+    Ok(status)
+}
+
+fn command_run_parallel(
+    config: Config,
+    targets: &[(String, Vec<String>)],
+    options: &RunOptions,
+    skip_status: ExitCode,
+) -> AppErrorResult<ExitCode> {
+    if let Some((name, _)) = targets
+        .iter()
+        .find(|(name, _)| !config.targets.contains_key(name))
+    {
+        return Err(AppError::unknown_target(name, config.targets.keys()));
+    }
+
+    for (name, target_args) in targets {
+        history::begin_target(name, target_args);
+    }
+    let mut args = options.to_args();
+    if skip_status != exitcode::OK {
+        args.push(format!("--skip-status={}", skip_status));
+    }
+    let names: Vec<_> = targets.iter().map(|(name, _)| name.as_str()).collect();
+    timings::record("parallel", &names.join(", "), || {
+        run_parallel(targets, &args, &AtomicBool::new(false))
+    })
+}
+
+fn command_run_args(
+    config_path: &Path,
+    config: Config,
+    mut run_args: RunArgs,
+    history_args: Vec<String>,
+) -> AppErrorResult<ExitCode> {
+    let (targets, more_extra_args) = args::split_extra_args(std::mem::take(&mut run_args.targets));
+    let (targets, run_options) = split_targets(&config, &targets);
+    // options given amongst the targets are parsed along with those before them
+    if !run_options.is_empty() {
+        let extra_args = std::mem::take(&mut run_args.extra_args);
+        let mut args = run_options;
+        args.extend(run_args.to_args());
+        run_args = RunArgs::try_from_args(&args)
+            .map_err(|err| AppError::usage(err.render().to_string().trim_end()))?;
+        run_args.extra_args = extra_args;
+    }
+    // asking for a target's help runs nothing, so isn't recorded either
+    for (target_name, target_args) in &targets {
+        if let Some(target_config) = config.targets.get(target_name) {
+            exit_if_help(target_name, target_config, config.shell, target_args);
+        }
+    }
+    if !run_args.dry_run {
+        history::begin(config_path, history_args);
+    }
+    let RunArgs {
+        targets: _,
+        options,
+        parallel,
+        dry_run,
+        keep_going,
+        skip_status,
+        fail_if_skipped,
+        timings,
+        trace,
+        events,
+        events_fd,
+        mut extra_args,
+    } = run_args;
+    events::init(events, events_fd)?;
+    // failing when skipped needs a status other than success
+    let skip_status = match fail_if_skipped {
+        true => 1,
+        false => skip_status,
+    };
+    extra_args.extend(more_extra_args);
+    // only a single target run on its own is given extra arguments, whether run or planned
+    if (parallel || targets.len() > 1) && !extra_args.is_empty() {
+        return Err(AppError::usage(match parallel {
+            true => "extra arguments cannot be given when running targets in parallel",
+            false => "extra arguments cannot be given when running multiple targets",
+        }));
+    }
+    let result = match (targets.as_slice(), parallel) {
+        (_, _) if dry_run && !targets.is_empty() => targets
+            .iter()
+            .try_for_each(|(target_name, target_args)| {
+                plan::print_plan(
+                    &config,
+                    target_name,
+                    target_args,
+                    &extra_args,
+                    options.timeout,
+                )
+            })
+            .map(|_| exitcode::OK),
+        ([], _) => {
+            println!("possible targets:");
+            for (name, _) in config.targets {
+                println!("  {}", name);
+            }
+            Err(AppError::usage("target not specified"))
+        }
+        ([(target_name, target_args)], false) => {
+            command_run_recorded(&config, target_name, target_args, extra_args, &options)
+                .map(|outcome| outcome.status(skip_status))
+        }
+        (_, true) => command_run_parallel(config, &targets, &options, skip_status),
+        (_, false) if options.watch => Err(AppError::usage(
+            "watching multiple targets requires '--parallel'",
+        )),
+        (_, false) => command_run_sequential(config, &targets, &options, keep_going, skip_status),
+    };
+    if timings {
+        timings::print_report();
+    }
+    if let Some(trace) = trace {
+        timings::write_trace(&trace)?;
+    }
+    result
+}
+
+fn command_rerun(config_path: &PathBuf, config: Config) -> AppErrorResult<ExitCode> {
+    let record = history::read()
+        .into_iter()
+        .rev()
+        .find(|record| record.config == *config_path)
+        .ok_or_else(|| AppError::new("nothing has been run yet", exitcode::NOINPUT))?;
+    log::info!("rerunning: run-tool run {}", record.args.join(" "));
+    let run_args = RunArgs::try_from_args(&record.args)
+        .map_err(|err| AppError::usage(err.render().to_string().trim_end()))?;
+    command_run_args(config_path, config, run_args, record.args)
+}
+
+fn command_history(
+    config_path: &PathBuf,
+    limit: usize,
+    target: Option<String>,
+    failed: bool,
+    all: bool,
+) -> AppErrorResult<()> {
+    let records: Vec<_> = history::read()
+        .into_iter()
+        .filter(|record| all || record.config == *config_path)
+        .filter(|record| {
+            target.as_ref().is_none_or(|name| {
+                record
+                    .targets
+                    .iter()
+                    .any(|t| t.name == *name || t.hooks.contains(name))
+            })
+        })
+        .filter(|record| !failed || record.status != exitcode::OK)
+        .collect();
+    let records = &records[records.len().saturating_sub(limit)..];
+    let outcomes: Vec<String> = records
+        .iter()
+        .map(|record| match record.status {
+            exitcode::OK if record.skipped() => String::from("skipped"),
+            status if record.skipped() => format!("skipped ({})", status),
+            exitcode::OK => String::from("ok"),
+            status => format!("failed ({})", status),
+        })
+        .collect();
+    let outcome_width = outcomes.iter().map(|o| o.len()).fold(6, usize::max);
+    println!(
+        "{:<19}  {:<outcome_width$}  {:>8}  command",
+        "started", "status", "duration"
+    );
+    for (record, outcome) in records.iter().zip(outcomes) {
+        let mut command = format!("run-tool run {}", record.args.join(" "));
+        if all {
+            command = format!("{} ({})", command, record.config.display());
+        }
+        println!(
+            "{}  {:<outcome_width$}  {:>7.1}s  {}",
+            record.started.format("%Y-%m-%d %H:%M:%S"),
+            outcome,
+            f64::from(record.duration),
+            command
+        );
+    }
     Ok(())
 }
 
 fn main() {
     let args = Args::parse();
-    let log_level = match args.verbose_logging {
-        true => log::Level::Debug,
-        false => log::Level::Info,
-    };
-    simple_logger::init_with_level(log_level).expect("failed to setup logging");
+    let log_level = helpers::get_log_level(args.verbose_logging, args.quiet_logging)
+        .unwrap_or_else(|msg| {
+            eprintln!("{}", msg);
+            exit(exitcode::USAGE);
+        });
+    let no_color = args.no_color || env::var_os(helpers::NO_COLOR_ENV_VAR).is_some();
+    if no_color {
+        helpers::disable_color();
+    }
+    simple_logger::SimpleLogger::new()
+        .with_level(log_level)
+        .with_colors(!no_color)
+        .init()
+        .expect("failed to setup logging");
 
     let lauched_from_dir = env::current_dir().unwrap_or_else(|_| {
         eprintln!("failed to get current working directory");
@@ -298,25 +978,26 @@ fn main() {
         });
     }
 
-    match args.command {
-        args::Command::Config { minimal } => command_config(config_path, selected_config, minimal),
-        args::Command::Run {
-            watch,
-            target_name,
-            extra_args,
-        } => match &target_name {
-            None => {
-                println!("possible targets:");
-                for (name, _) in selected_config.targets {
-                    println!("  {}", name);
-                }
-                Err(AppError {
-                    msg: String::from("target not specified"),
-                    exitcode: exitcode::USAGE,
-                })
-            }
-            Some(target_name) => command_run(selected_config, &target_name, extra_args, watch),
-        },
-    }
-    .unwrap_or_else(|err| err.handle());
+    let result = match args.command {
+        args::Command::Config { minimal } => {
+            command_config(config_path, selected_config, minimal).map(|_| exitcode::OK)
+        }
+        args::Command::Run(run_args) => {
+            let history_args = run_args.to_args();
+            command_run_args(&config_path, selected_config, run_args, history_args)
+        }
+        args::Command::Rerun => command_rerun(&config_path, selected_config),
+        args::Command::History {
+            limit,
+            target,
+            failed,
+            all,
+        } => command_history(&config_path, limit, target, failed, all).map(|_| exitcode::OK),
+    };
+    history::finish(match &result {
+        Ok(status) => *status,
+        Err(err) => err.exitcode,
+    });
+    let status = result.unwrap_or_else(|err| err.handle());
+    exit(status);
 }
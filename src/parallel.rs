@@ -0,0 +1,142 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    process::Child,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::{self, sleep, JoinHandle},
+    time::Duration,
+};
+
+use colored::{Color, Colorize};
+use exitcode::ExitCode;
+
+use crate::errors::{AppError, AppErrorResult, ErrorContext};
+use crate::runner::{
+    catch_interrupts, exit_if_interrupted, interrupted, stop_process_group, ProcessRunner, Stopping,
+};
+
+const LABEL_COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Green,
+    Color::Blue,
+    Color::Red,
+];
+
+fn forward_output(
+    output: impl Read + Send + 'static,
+    label: String,
+    to_stderr: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).unwrap_or_default() != 0 {
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            let _ = match to_stderr {
+                true => write_line(&mut io::stderr().lock(), &label, &line),
+                false => write_line(&mut io::stdout().lock(), &label, &line),
+            };
+            line.clear();
+        }
+    })
+}
+
+fn write_line(out: &mut impl Write, label: &str, line: &[u8]) -> io::Result<()> {
+    out.write_all(label.as_bytes())?;
+    out.write_all(line)?;
+    out.flush()
+}
+
+/// Runs targets at the same time with their output prefixed by the target name,
+/// stopping all of them once one fails, the user interrupts or `cancel` is set.
+/// Each target is given `run_args` before its name, followed by its own arguments
+pub fn run_parallel(
+    targets: &[(String, Vec<String>)],
+    run_args: &[String],
+    cancel: &AtomicBool,
+) -> AppErrorResult<ExitCode> {
+    catch_interrupts();
+
+    let label_width = targets
+        .iter()
+        .map(|(n, _)| n.len())
+        .max()
+        .unwrap_or_default();
+    // children are kept once exited, as what they started stays in their process group
+    let mut children: Vec<(&String, Child, bool)> = Vec::new();
+    let mut output_threads = Vec::new();
+    let mut status = exitcode::OK;
+    let mut stopping = Stopping::default();
+
+    for (i, (name, target_args)) in targets.iter().enumerate() {
+        let mut args = vec![String::from("run")];
+        args.extend(run_args.iter().cloned());
+        args.push(name.to_owned());
+        args.extend(target_args.iter().cloned());
+        let child = ProcessRunner::nested(args).spawn_captured();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => {
+                status = exitcode::SOFTWARE;
+                log::error!("{}", err.context(ErrorContext::Target(name.to_owned())));
+                break;
+            }
+        };
+        let label = format!("{:<width$} | ", name, width = label_width)
+            .color(LABEL_COLORS[i % LABEL_COLORS.len()])
+            .to_string();
+        if let Some(stdout) = child.stdout.take() {
+            output_threads.push(forward_output(stdout, label.clone(), false));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            output_threads.push(forward_output(stderr, label, true));
+        }
+        children.push((name, child, true));
+    }
+
+    loop {
+        for (name, child, running) in children.iter_mut().filter(|(_, _, running)| *running) {
+            let exited = child
+                .try_wait()
+                .map_err(|err| AppError::process("wait for", name, err))?;
+            if let Some(exit_status) = exited {
+                let code = exit_status.code().unwrap_or_default();
+                if exitcode::is_error(code) && !stopping.started() {
+                    log::error!("target '{}' exited with status {}, stopping", name, code);
+                    status = code;
+                } else {
+                    log::debug!("target '{}' exited with status {}", name, code);
+                }
+                *running = false;
+            }
+        }
+        // output is also read until whatever the targets started in the background exits
+        if children.iter().all(|(_, _, running)| !running)
+            && output_threads.iter().all(|thread| thread.is_finished())
+        {
+            break;
+        }
+
+        let should_stop = status != exitcode::OK || interrupted() || cancel.load(Ordering::Relaxed);
+        stopping.poll(should_stop, |force| {
+            for (_, child, _) in children.iter_mut() {
+                stop_process_group(child, force);
+            }
+        });
+        // what is still writing output left the process groups, so can't be stopped
+        if stopping.overdue() {
+            log::warn!("stopped waiting for the output of processes that could not be stopped");
+            break;
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    for output_thread in output_threads.into_iter().filter(|t| t.is_finished()) {
+        let _ = output_thread.join();
+    }
+    exit_if_interrupted();
+    Ok(status)
+}
@@ -0,0 +1,488 @@
+use std::fmt::Write;
+
+use clap::{builder::PossibleValuesParser, error::ErrorKind, Arg, ArgAction, CommandFactory};
+
+use crate::args::Args;
+use crate::config::{Config, ExecCommand, ExecConfig, ParamType, Shell, TargetConfig};
+use crate::errors::{AppError, AppErrorResult};
+use crate::helpers::{interpolate, quote_arg, similar_names, EnvVars};
+
+/// Splits the command line into each target and the arguments given to it,
+/// along with any options for `run` given amongst them.
+/// A new target starts at an argument matching a target name, unless it is the value
+/// of a parameter given by name or of a required positional parameter.
+/// The first target can be shortened, as long as only one target's name starts with it
+pub fn split_targets(
+    config: &Config,
+    args: &[String],
+) -> (Vec<(String, Vec<String>)>, Vec<String>) {
+    let command = Args::command();
+    let run_options: Vec<&Arg> = command
+        .find_subcommand("run")
+        .expect("run is a subcommand")
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && arg.get_id() != "help")
+        .collect();
+    let mut targets: Vec<(String, Vec<String>)> = Vec::new();
+    let mut options = Vec::new();
+    // positional parameters of the current target still needing a value
+    let mut positionals_needed = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some((name, target_args)) = targets.last_mut() else {
+            let name = expand_target_name(config, arg);
+            positionals_needed = required_positionals(config, &name);
+            targets.push((name, Vec::new()));
+            continue;
+        };
+        let params = config
+            .targets
+            .get(name)
+            .map(|target_config| target_config.params.as_slice())
+            .unwrap_or_default();
+        // parameters take priority over options of the same name
+        if let Some(param) = params
+            .iter()
+            .find(|param| !param.positional && arg.strip_prefix("--") == Some(&param.name))
+        {
+            target_args.push(arg.to_owned());
+            if param.param_type != ParamType::Boolean {
+                target_args.extend(args.next().cloned());
+            }
+            continue;
+        }
+        if let Some(takes_value) = run_option(&run_options, arg) {
+            options.push(arg.to_owned());
+            if takes_value {
+                options.extend(args.next().cloned());
+            }
+            continue;
+        }
+        if positionals_needed > 0 && !arg.starts_with('-') {
+            positionals_needed -= 1;
+            target_args.push(arg.to_owned());
+        } else if config.targets.contains_key(arg) {
+            positionals_needed = required_positionals(config, arg);
+            targets.push((arg.to_owned(), Vec::new()));
+        } else {
+            target_args.push(arg.to_owned());
+        }
+    }
+    (targets, options)
+}
+
+/// Number of positional parameters the target must be given
+fn required_positionals(config: &Config, target_name: &str) -> usize {
+    config
+        .targets
+        .get(target_name)
+        .map(|target_config| {
+            target_config
+                .params
+                .iter()
+                .filter(|param| param.positional && param.required && param.default.is_none())
+                .count()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the argument is one of the options for `run`,
+/// giving if its value is the next argument
+fn run_option(run_options: &[&Arg], arg: &str) -> Option<bool> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let (long, value) = match long.split_once('=') {
+            Some((long, _)) => (long, true),
+            None => (long, false),
+        };
+        let option = run_options
+            .iter()
+            .find(|option| option.get_long() == Some(long))?;
+        return Some(
+            !value && option.get_action().takes_values() && !option.is_require_equals_set(),
+        );
+    }
+    // only flags can be combined e.g. `-wk`
+    let shorts = arg.strip_prefix('-').filter(|shorts| !shorts.is_empty())?;
+    shorts
+        .chars()
+        .all(|short| {
+            run_options.iter().any(|option| {
+                option.get_short() == Some(short) && !option.get_action().takes_values()
+            })
+        })
+        .then_some(false)
+}
+
+/// Suggests the target an argument was probably meant to be,
+/// when it was taken as a parameter that could not be parsed
+pub fn hint_misspelt_target(config: &Config, args: &[String], err: AppError) -> AppError {
+    if err.exitcode != exitcode::USAGE || err.hint.is_some() {
+        return err;
+    }
+    let names = config.targets.keys().map(String::as_str);
+    let misspelt = args
+        .iter()
+        .filter(|arg| !arg.starts_with('-'))
+        .find_map(|arg| Some((arg, *similar_names(arg, names.clone()).first()?)));
+    match misspelt {
+        Some((arg, target)) => err.with_hint(format!(
+            "'{}' is not a target, did you mean '{}'?",
+            arg, target
+        )),
+        None => err,
+    }
+}
+
+/// Gets the only target whose name starts with the given one, when it is not a full name.
+/// Later targets are not expanded, as they could be mistaken for a parameter's value
+fn expand_target_name(config: &Config, name: &str) -> String {
+    if name.is_empty() || config.targets.contains_key(name) {
+        return name.to_owned();
+    }
+    let mut starting_with = config.targets.keys().filter(|t| t.starts_with(name));
+    match (starting_with.next(), starting_with.next()) {
+        (Some(target), None) => {
+            log::debug!("expanded '{}' to target '{}'", name, target);
+            target.to_owned()
+        }
+        _ => name.to_owned(),
+    }
+}
+
+/// Writes the command line that will be run, with the parameters filled in
+fn write_exec(
+    out: &mut String,
+    exec: &ExecConfig,
+    default_shell: Shell,
+    params: &EnvVars,
+    indent: &str,
+) -> std::fmt::Result {
+    match &exec.command {
+        ExecCommand::Program { program, args } => {
+            write!(
+                out,
+                "{}{}",
+                indent,
+                quote_arg(&interpolate(program, params))
+            )?;
+            for arg in args {
+                write!(out, " {}", quote_arg(&interpolate(arg, params)))?;
+            }
+            writeln!(out)?;
+        }
+        ExecCommand::Script {
+            script,
+            interpreter,
+        } => {
+            writeln!(
+                out,
+                "{}{} <script>:",
+                indent,
+                interpolate(interpreter, params)
+            )?;
+            for line in interpolate(script, params).lines() {
+                writeln!(out, "{}  {}", indent, line)?;
+            }
+        }
+        ExecCommand::Shell { cmd, shell } => {
+            let shell = format!("{:?}", shell.unwrap_or(default_shell)).to_lowercase();
+            let cmd = interpolate(cmd, params);
+            match cmd.trim_end().contains('\n') {
+                false => writeln!(out, "{}{}: {}", indent, shell, cmd.trim_end())?,
+                true => {
+                    writeln!(out, "{}{}:", indent, shell)?;
+                    for line in cmd.lines() {
+                        writeln!(out, "{}  {}", indent, line)?;
+                    }
+                }
+            }
+        }
+    }
+    if let Some(cwd) = &exec.cwd {
+        writeln!(out, "{}  in: {}", indent, interpolate(cwd, params))?;
+    }
+    for (name, redirect) in [
+        ("stdin", &exec.stdin),
+        ("stdout", &exec.stdout),
+        ("stderr", &exec.stderr),
+    ] {
+        if let Some(redirect) = redirect {
+            writeln!(out, "{}  {}: {}", indent, name, redirect)?;
+        }
+    }
+    if let Some(timeout) = exec.timeout {
+        writeln!(out, "{}  timeout: {}s", indent, f64::from(timeout))?;
+    }
+    let mut env: Vec<_> = exec.env.iter().collect();
+    env.sort();
+    for (key, value) in env {
+        writeln!(
+            out,
+            "{}  env: {}={}",
+            indent,
+            key,
+            interpolate(value, params)
+        )?;
+    }
+    Ok(())
+}
+
+/// Describes everything the target does, shown in its long help
+fn write_details(
+    out: &mut String,
+    target_config: &TargetConfig,
+    default_shell: Shell,
+) -> std::fmt::Result {
+    // show the defaults in place of the parameters, when there are any
+    let params: EnvVars = target_config
+        .params
+        .iter()
+        .filter_map(|param| Some((param.name.to_owned(), param.default.as_ref()?.to_string())))
+        .collect();
+    if let Some(exec) = &target_config.exec {
+        writeln!(out, "Command:")?;
+        write_exec(out, exec, default_shell, &params, "  ")?;
+    }
+    if !target_config.steps.is_empty() {
+        writeln!(out, "Steps:")?;
+        for (i, step) in target_config.steps.iter().enumerate() {
+            if let Some(name) = &step.name {
+                writeln!(out, "  {}. {}", i + 1, name)?;
+                write_exec(out, &step.exec, default_shell, &params, "     ")?;
+            } else {
+                write!(out, "  {}.", i + 1)?;
+                write_exec(out, &step.exec, default_shell, &params, " ")?;
+            }
+        }
+    }
+    if !target_config.parallel.is_empty() {
+        writeln!(out, "Parallel:\n  {}", target_config.parallel.join(", "))?;
+    }
+    if !target_config.pipe.is_empty() {
+        writeln!(out, "Pipe:\n  {}", target_config.pipe.join(" | "))?;
+    }
+    if let Some(log) = &target_config.log {
+        writeln!(out, "Log file:\n  {}", interpolate(&log.file, &params))?;
+    }
+    if let Some(retry) = &target_config.retry {
+        writeln!(
+            out,
+            "Retries:\n  {} attempts, {}s apart (x{} backoff)",
+            retry.attempts,
+            f64::from(retry.delay),
+            retry.backoff
+        )?;
+    }
+    if !target_config.before_hooks.is_empty() {
+        writeln!(
+            out,
+            "Before hooks:\n  {}",
+            target_config.before_hooks.join(", ")
+        )?;
+    }
+    if !target_config.after_hooks.is_empty() {
+        writeln!(
+            out,
+            "After hooks:\n  {}",
+            target_config.after_hooks.join(", ")
+        )?;
+    }
+    if !target_config.run_when.is_empty() {
+        writeln!(out, "Runs when:")?;
+        for check in &target_config.run_when {
+            writeln!(out, "  {}", check)?;
+        }
+    }
+    if !target_config.watch.paths.is_empty() {
+        writeln!(out, "Watch paths:")?;
+        for path in &target_config.watch.paths {
+            writeln!(out, "  {}", path.display())?;
+        }
+    }
+    Ok(())
+}
+
+/// Builds a command line parser for the target's parameters,
+/// with help describing the target
+pub fn params_command(
+    target_name: &str,
+    target_config: &TargetConfig,
+    default_shell: Shell,
+) -> clap::Command {
+    let mut details = String::new();
+    let _ = write_details(&mut details, target_config, default_shell);
+    let mut command = clap::Command::new(target_name.to_owned())
+        .bin_name(format!("run-tool run {}", target_name))
+        .no_binary_name(true)
+        .disable_version_flag(true)
+        .after_long_help(details.trim_end().to_owned());
+    if let Some(description) = &target_config.description {
+        command = command.about(description.to_owned());
+    }
+    if let Some(usage) = &target_config.usage {
+        let long_about = match &target_config.description {
+            Some(description) => format!("{}\n\n{}", description, usage.trim_end()),
+            None => usage.trim_end().to_owned(),
+        };
+        command = command.long_about(long_about);
+    }
+    for param in &target_config.params {
+        let mut arg = Arg::new(param.name.to_owned());
+        if let Some(help) = &param.help {
+            arg = arg.help(help.to_owned());
+        }
+        if !param.positional {
+            arg = arg.long(param.name.to_owned());
+        }
+        arg = match param.param_type {
+            ParamType::Boolean => arg.action(ArgAction::SetTrue),
+            _ if !param.choices.is_empty() => arg.value_parser(PossibleValuesParser::new(
+                param.choices.iter().map(|choice| choice.to_string()),
+            )),
+            ParamType::Integer => arg.value_parser(clap::value_parser!(i64)),
+            ParamType::Number => arg.value_parser(clap::value_parser!(f64)),
+            ParamType::String => arg,
+        };
+        if let Some(default) = &param.default {
+            arg = arg.default_value(default.to_string());
+        } else if param.required {
+            arg = arg.required(true);
+        }
+        command = command.arg(arg);
+    }
+    command
+}
+
+/// Shows the target's help and exits, when the arguments given to it ask for it.
+/// Any other problem with them is left for when its parameters are parsed
+pub fn exit_if_help(
+    target_name: &str,
+    target_config: &TargetConfig,
+    default_shell: Shell,
+    args: &[String],
+) {
+    if let Err(err) =
+        params_command(target_name, target_config, default_shell).try_get_matches_from(args)
+    {
+        if err.kind() == ErrorKind::DisplayHelp {
+            err.exit();
+        }
+    }
+}
+
+/// Parses the target's parameters from the arguments given to it
+pub fn parse_params(
+    target_name: &str,
+    target_config: &TargetConfig,
+    default_shell: Shell,
+    args: &[String],
+) -> AppErrorResult<EnvVars> {
+    let matches = params_command(target_name, target_config, default_shell)
+        .try_get_matches_from(args)
+        .map_err(|err| AppError::usage(err.render().to_string().trim_end()))?;
+    Ok(target_config
+        .params
+        .iter()
+        .map(|param| {
+            // optional parameters that were not given are left empty
+            let value = match param.param_type {
+                ParamType::Boolean => matches.get_flag(&param.name).to_string(),
+                _ => matches
+                    .get_raw(&param.name)
+                    .and_then(|mut values| values.next())
+                    .map(|value| value.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            };
+            (param.name.to_owned(), value)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        serde_yml::from_str(
+            r#"
+targets:
+  test:
+    program: "true"
+  deploy:
+    params:
+      - name: env
+      - name: dry
+        type: boolean
+    program: "true"
+  greet:
+    params:
+      - name: who
+        positional: true
+        required: true
+    program: "true"
+"#,
+        )
+        .unwrap()
+    }
+
+    fn split(args: &[&str]) -> (Vec<(String, Vec<String>)>, Vec<String>) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        split_targets(&config(), &args)
+    }
+
+    fn target(name: &str, args: &[&str]) -> (String, Vec<String>) {
+        (
+            name.to_owned(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn splits_at_target_names() {
+        let (targets, options) = split(&["test", "deploy", "--dry", "test"]);
+        assert_eq!(
+            targets,
+            [
+                target("test", &[]),
+                target("deploy", &["--dry"]),
+                target("test", &[])
+            ]
+        );
+        assert!(options.is_empty());
+    }
+
+    #[test]
+    fn keeps_parameter_values_matching_target_names() {
+        let (targets, _) = split(&["deploy", "--env", "test", "test"]);
+        assert_eq!(
+            targets,
+            [target("deploy", &["--env", "test"]), target("test", &[])]
+        );
+    }
+
+    #[test]
+    fn keeps_required_positional_values_matching_target_names() {
+        let (targets, _) = split(&["greet", "test", "test"]);
+        assert_eq!(targets, [target("greet", &["test"]), target("test", &[])]);
+    }
+
+    #[test]
+    fn takes_run_options_after_targets() {
+        let (targets, options) = split(&["test", "-w", "--timeout", "5", "--poll=2", "deploy"]);
+        assert_eq!(targets, [target("test", &[]), target("deploy", &[])]);
+        assert_eq!(options, ["-w", "--timeout", "5", "--poll=2"]);
+    }
+
+    #[test]
+    fn leaves_unknown_options_to_the_target() {
+        let (targets, options) = split(&["test", "--nope", "-x"]);
+        assert_eq!(targets, [target("test", &["--nope", "-x"])]);
+        assert!(options.is_empty());
+    }
+
+    #[test]
+    fn expands_only_the_first_target_name() {
+        let (targets, _) = split(&["te", "gr", "x"]);
+        assert_eq!(targets, [target("test", &["gr", "x"])]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::Duration,
+};
+
+use exitcode::ExitCode;
+
+use crate::config::PipeStatus;
+use crate::errors::{AppError, AppErrorResult};
+use crate::runner::{
+    catch_interrupts, exit_if_interrupted, interrupted, target_command, PipedTarget, Stopping,
+};
+
+/// Runs targets with the output of each given as the input of the next,
+/// stopping all of them when one cannot be started, the user interrupts or `cancel` is set.
+/// Each target is given `run_args` before its name
+pub fn run_pipeline(
+    target_names: &[String],
+    run_args: &[String],
+    pipe_status: PipeStatus,
+    cancel: &AtomicBool,
+) -> AppErrorResult<ExitCode> {
+    catch_interrupts();
+
+    // each target along with its status once exited
+    let mut targets: Vec<(PipedTarget, Option<ExitCode>)> = Vec::new();
+    let mut error = None;
+    for (i, name) in target_names.iter().enumerate() {
+        let mut cmd = target_command(name, run_args);
+        if let Some((previous, _)) = targets.last_mut() {
+            if let Some(stdout) = previous.child.stdout.take() {
+                cmd.stdin(stdout);
+            }
+        }
+        if i + 1 < target_names.len() {
+            cmd.stdout(Stdio::piped());
+        }
+        match PipedTarget::spawn(&mut cmd, name, i == 0) {
+            Ok(target) => targets.push((target, None)),
+            Err(err) => {
+                error = Some(err);
+                break;
+            }
+        }
+    }
+
+    let mut stopping = Stopping::default();
+    while targets.iter().any(|(_, status)| status.is_none()) {
+        for (target, status) in targets.iter_mut().filter(|(_, status)| status.is_none()) {
+            match target.child.try_wait() {
+                Ok(Some(exit_status)) => {
+                    let code = exit_status.code().unwrap_or_default();
+                    if exitcode::is_error(code) && !stopping.started() {
+                        log::error!("'{}' in pipeline exited with status {}", target.name, code);
+                    }
+                    *status = Some(code);
+                }
+                Ok(None) => (),
+                Err(err) => {
+                    error.get_or_insert(AppError::process("wait for", &target.name, err));
+                    *status = Some(exitcode::OSERR);
+                }
+            }
+        }
+
+        let should_stop = error.is_some() || interrupted() || cancel.load(Ordering::Relaxed);
+        stopping.poll(should_stop, |force| {
+            for (target, _) in targets.iter_mut().filter(|(_, status)| status.is_none()) {
+                target.stop(force);
+            }
+        });
+        sleep(Duration::from_millis(1));
+    }
+
+    exit_if_interrupted();
+    if let Some(err) = error {
+        return Err(err);
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return Ok(exitcode::OK);
+    }
+    let mut statuses = targets.iter().flat_map(|(_, status)| *status).rev();
+    Ok(match pipe_status {
+        PipeStatus::Last => statuses.next().unwrap_or_default(),
+        PipeStatus::AnyFail => statuses
+            .find(|status| exitcode::is_error(*status))
+            .unwrap_or(exitcode::OK),
+    })
+}
@@ -0,0 +1,167 @@
+use std::{collections::BTreeMap, env};
+
+use crate::config::{Config, Seconds, TargetCheck};
+use crate::errors::{AppError, AppErrorResult};
+use crate::helpers::{interpolate, quote_arg};
+use crate::params::{hint_misspelt_target, parse_params};
+use crate::runner::ProcessRunner;
+
+fn print_runner(runner: &ProcessRunner, indent: usize) {
+    println!("{:indent$}program: {}", "", quote_arg(&runner.program));
+    if !runner.args.is_empty() {
+        let args: Vec<String> = runner.args.iter().map(|arg| quote_arg(arg)).collect();
+        println!("{:indent$}args: {}", "", args.join(" "));
+    }
+    if let Some(cwd) = &runner.cwd {
+        println!("{:indent$}cwd: {}", "", cwd);
+    }
+    for (name, redirect) in [
+        ("stdin", &runner.stdin),
+        ("stdout", &runner.stdout),
+        ("stderr", &runner.stderr),
+    ] {
+        if let Some(redirect) = redirect {
+            println!("{:indent$}{}: {}", "", name, redirect);
+        }
+    }
+    if let Some(timeout) = runner.timeout {
+        println!("{:indent$}timeout: {:.1}s", "", timeout.as_secs_f64());
+    }
+    // only show variables that differ from the current environment
+    let vars: BTreeMap<_, _> = runner.vars.iter().collect();
+    for (key, value) in vars {
+        match env::var(key) {
+            Err(_) => println!("{:indent$}env: +{}={}", "", key, value),
+            Ok(current) if current != *value => {
+                println!("{:indent$}env: ~{}={} (was {})", "", key, value, current)
+            }
+            Ok(_) => (),
+        }
+    }
+}
+
+fn print_target(
+    config: &Config,
+    target_name: &str,
+    target_args: &[String],
+    extra_args: &[String],
+    timeout: Option<Seconds>,
+    indent: usize,
+    parents: &mut Vec<String>,
+) -> AppErrorResult<()> {
+    println!("{:indent$}{}", "", target_name);
+    let indent = indent + 2;
+    if parents.iter().any(|parent| parent == target_name) {
+        println!("{:indent$}(already running, would never finish)", "");
+        return Ok(());
+    }
+    let target_config = config
+        .targets
+        .get(target_name)
+        .ok_or_else(|| AppError::unknown_target(target_name, config.targets.keys()))?;
+    let params = parse_params(target_name, target_config, config.shell, target_args)
+        .map_err(|err| hint_misspelt_target(config, target_args, err))?;
+    parents.push(target_name.to_owned());
+
+    if !target_config.run_when.is_empty() {
+        println!("{:indent$}checks:", "");
+        let mut skipped = false;
+        for check in &target_config.run_when {
+            let result = match &check.when {
+                TargetCheck::ExecOk(_) => "not run",
+                TargetCheck::PathExists { path } if path.exists() != check.invert => "passed",
+                TargetCheck::PathIsFile { path } if path.is_file() != check.invert => "passed",
+                TargetCheck::PathIsDir { path } if path.is_dir() != check.invert => "passed",
+                _ => {
+                    skipped = true;
+                    "failed"
+                }
+            };
+            println!("{:indent$}  {}: {}", "", check, result);
+        }
+        if skipped {
+            println!("{:indent$}(skipped, as a check failed)", "");
+            parents.pop();
+            return Ok(());
+        }
+    }
+    if !target_config.before_hooks.is_empty() {
+        println!("{:indent$}before hooks:", "");
+        for hook in &target_config.before_hooks {
+            print_target(config, hook, &[], &[], timeout, indent + 2, parents)?;
+        }
+    }
+    if !target_config.parallel.is_empty() {
+        println!("{:indent$}parallel:", "");
+        for name in &target_config.parallel {
+            print_target(config, name, &[], &[], timeout, indent + 2, parents)?;
+        }
+    }
+    if !target_config.pipe.is_empty() {
+        println!("{:indent$}pipe:", "");
+        for name in &target_config.pipe {
+            print_target(config, name, &[], &[], timeout, indent + 2, parents)?;
+        }
+    }
+    if let Some(exec) = &target_config.exec {
+        print_runner(
+            &ProcessRunner::from_exec_unwritten(exec, config.shell, &params, extra_args)?
+                .with_timeout(timeout),
+            indent,
+        );
+    }
+    if let Some(log) = &target_config.log {
+        println!("{:indent$}log: {}", "", interpolate(&log.file, &params));
+    }
+    if let Some(retry) = &target_config.retry {
+        println!(
+            "{:indent$}retry: {} attempts, {}s apart (x{} backoff)",
+            "",
+            retry.attempts,
+            f64::from(retry.delay),
+            retry.backoff
+        );
+    }
+    if !target_config.steps.is_empty() {
+        println!("{:indent$}steps:", "");
+        for (i, step) in target_config.steps.iter().enumerate() {
+            match &step.name {
+                Some(name) => println!("{:indent$}  {}. {}", "", i + 1, name),
+                None => println!("{:indent$}  {}.", "", i + 1),
+            }
+            print_runner(
+                &ProcessRunner::from_exec_unwritten(&step.exec, config.shell, &params, &[])?
+                    .with_timeout(timeout),
+                indent + 5,
+            );
+        }
+    }
+    if !target_config.after_hooks.is_empty() {
+        println!("{:indent$}after hooks:", "");
+        for hook in &target_config.after_hooks {
+            print_target(config, hook, &[], &[], timeout, indent + 2, parents)?;
+        }
+    }
+    parents.pop();
+    Ok(())
+}
+
+/// Prints what running the target would do, in the order it would be done,
+/// without running anything. A `timeout` overrides those configured, as when run
+pub fn print_plan(
+    config: &Config,
+    target_name: &str,
+    target_args: &[String],
+    extra_args: &[String],
+    timeout: Option<Seconds>,
+) -> AppErrorResult<()> {
+    print_target(
+        config,
+        target_name,
+        target_args,
+        extra_args,
+        timeout,
+        0,
+        &mut Vec::new(),
+    )
+}
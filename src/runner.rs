@@ -1,76 +1,799 @@
-use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread::sleep;
-use std::time::Duration;
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
 
 use exitcode::ExitCode;
 
-use crate::errors::{AppError, AppErrorResult};
-use crate::helpers::EnvVars;
+#[cfg(unix)]
+use crate::config::Signal;
+use crate::config::{ExecCommand, ExecConfig, Redirect, Seconds, Shell, Stream};
+use crate::errors::{AppError, AppErrorResult, ErrorContext};
+use crate::helpers::{self, get_app_binary_path, interpolate, EnvVars};
+use crate::logfile::{forward_to_log, SharedLogFile};
+use crate::{events, history};
+
+/// How long stopping processes have to exit, before being killed
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// Exit status used by shells when interrupted by the user
+pub const INTERRUPTED_EXIT_CODE: ExitCode = 130;
+/// Exit status used when stopped for taking too long, the same as the `timeout` command
+pub const TIMED_OUT_EXIT_CODE: ExitCode = 124;
+
+static SCRIPT_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_HANDLER: Once = Once::new();
+
+/// How many names are tried for a script file, before giving up
+const SCRIPT_FILE_ATTEMPTS: usize = 100;
+
+/// A script written to a temporary file, which is removed when dropped
+pub struct ScriptFile(PathBuf);
+
+impl ScriptFile {
+    /// Writes the script to a new file only readable by the user,
+    /// named with the extension the interpreter expects
+    pub fn new(script: &str, interpreter: &str) -> AppErrorResult<Self> {
+        let extension = script_extension(interpreter)
+            .map(|extension| format!(".{}", extension))
+            .unwrap_or_default();
+        let mut attempts = 0;
+        loop {
+            let path = env::temp_dir().join(format!(
+                "run-tool-{}-{}{}",
+                process::id(),
+                SCRIPT_FILE_COUNT.fetch_add(1, Ordering::Relaxed),
+                extension
+            ));
+            let mut options = OpenOptions::new();
+            // never follows or reuses a file someone else has created
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            match options.open(&path) {
+                Ok(mut file) => {
+                    let script_file = Self(path);
+                    file.write_all(script.as_bytes()).map_err(|err| {
+                        AppError::file("write script to", &script_file.0, err, exitcode::CANTCREAT)
+                    })?;
+                    return Ok(script_file);
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::AlreadyExists
+                        && attempts < SCRIPT_FILE_ATTEMPTS =>
+                {
+                    attempts += 1;
+                }
+                Err(err) => {
+                    return Err(AppError::file(
+                        "write script to",
+                        &path,
+                        err,
+                        exitcode::CANTCREAT,
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The file extension a script is expected to have by its interpreter,
+/// some choose how to run it by the extension
+fn script_extension(interpreter: &str) -> Option<&'static str> {
+    let name = Path::new(interpreter).file_stem()?.to_str()?;
+    // versioned names e.g. `python3.12`
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(match name {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" => "sh",
+        "fish" => "fish",
+        "pwsh" | "powershell" => "ps1",
+        "python" | "pypy" => "py",
+        "node" => "js",
+        "deno" | "bun" | "tsx" | "ts-node" => "ts",
+        "ruby" => "rb",
+        "perl" => "pl",
+        "php" => "php",
+        "lua" | "luajit" => "lua",
+        "Rscript" => "R",
+        "julia" => "jl",
+        _ => return None,
+    })
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
 
 pub struct ProcessRunner {
     pub program: String,
     pub args: Vec<String>,
     pub vars: EnvVars,
     pub cwd: Option<String>,
+    pub script_file: Option<ScriptFile>,
+    pub timeout: Option<Duration>,
+    pub log_file: Option<SharedLogFile>,
+    pub stdin: Option<Redirect>,
+    pub stdout: Option<Redirect>,
+    pub stderr: Option<Redirect>,
+    /// Whether the program is run-tool itself, running a target as part of this run
+    pub nested: bool,
 }
 
-impl ProcessRunner {
-    /// Runs the process interactively allowing user to see stdout and use stdin
-    pub fn run_interactive(&self) -> AppErrorResult<ExitCode> {
-        let mut cmd = Command::new(&self.program);
-        cmd.envs(&self.vars).args(&self.args);
-        if let Some(cwd) = &self.cwd {
-            cmd.current_dir(cwd);
+/// A started process, along with anything connected to it
+struct RunningProcess {
+    child: Child,
+    /// Whether the process is in its own process group, which is stopped along with it
+    own_group: bool,
+    /// Whether its process group has been given the terminal, to take back once it exits
+    foreground: bool,
+    output_threads: Vec<JoinHandle<()>>,
+    piped_targets: Vec<PipedTarget>,
+}
+
+/// Stops processes, asking them to first then killing them
+/// once they have had `STOP_GRACE_PERIOD` to exit
+#[derive(Default)]
+pub struct Stopping {
+    since: Option<Instant>,
+    killed: bool,
+}
+
+impl Stopping {
+    /// Calls `stop` once `should_stop` is first set, with whether to kill the processes,
+    /// then again once the grace period is over
+    pub fn poll(&mut self, should_stop: bool, mut stop: impl FnMut(bool)) {
+        match self.since {
+            None if should_stop => {
+                self.since = Some(Instant::now());
+                stop(false);
+            }
+            Some(since) if !self.killed && since.elapsed() > STOP_GRACE_PERIOD => {
+                self.killed = true;
+                stop(true);
+            }
+            _ => (),
         }
-        Ok(cmd
-            .spawn()
-            .map_err(|err| AppError {
-                msg: format!("{}", err),
-                exitcode: exitcode::SOFTWARE,
-            })?
+    }
+
+    /// Whether the processes have been asked to stop
+    pub fn started(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// Whether something is still running long after the processes were killed
+    pub fn overdue(&self) -> bool {
+        self.since
+            .is_some_and(|since| since.elapsed() > STOP_GRACE_PERIOD * 2)
+    }
+}
+
+/// A target started to give a process its input or take its output,
+/// which is stopped when dropped whilst still running
+pub struct PipedTarget {
+    pub name: String,
+    pub child: Child,
+    /// Whether it is in its own process group, which is stopped along with it
+    own_group: bool,
+}
+
+impl PipedTarget {
+    /// Starts the target, on unix in its own process group
+    /// unless it takes its input from a terminal
+    pub fn spawn(cmd: &mut Command, name: &str, inherits_stdin: bool) -> AppErrorResult<Self> {
+        let own_group = cfg!(unix) && !(inherits_stdin && io::stdin().is_terminal());
+        #[cfg(unix)]
+        if own_group {
+            use std::os::unix::process::CommandExt;
+            // interrupts no longer reach the process group, so must be forwarded
+            catch_interrupts();
+            cmd.process_group(0);
+        }
+        let child = cmd.spawn().map_err(|err| {
+            AppError::spawn(&cmd.get_program().to_string_lossy(), None, err)
+                .context(ErrorContext::Target(name.to_owned()))
+        })?;
+        Ok(Self {
+            name: name.to_owned(),
+            child,
+            own_group,
+        })
+    }
+
+    /// Waits for the target to exit, giving its status
+    fn wait(&mut self) -> ExitCode {
+        self.child
             .wait()
-            .map_err(|_| AppError {
-                msg: String::from("failed to execute"),
-                exitcode: exitcode::OSERR,
+            .map(|status| status.code().unwrap_or_default())
+            .unwrap_or(exitcode::OSERR)
+    }
+
+    /// Asks the target to stop, or kills it when `force` is set.
+    /// Interrupts already reach it when it isn't in its own process group
+    pub fn stop(&mut self, force: bool) {
+        if force || self.own_group || !interrupted() {
+            stop_process(&mut self.child, self.own_group, force);
+        }
+    }
+}
+
+impl Drop for PipedTarget {
+    fn drop(&mut self) {
+        if !matches!(self.child.try_wait(), Ok(None)) {
+            return;
+        }
+        log::debug!("stopping piped target '{}'", self.name);
+        let mut stopping = Stopping::default();
+        while matches!(self.child.try_wait(), Ok(None)) {
+            stopping.poll(true, |force| self.stop(force));
+            sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+/// Fills in the parameters of a file path
+fn interpolate_redirect(redirect: &Option<Redirect>, params: &EnvVars) -> Option<Redirect> {
+    match redirect {
+        Some(Redirect::File { file, append }) => Some(Redirect::File {
+            file: interpolate(file, params),
+            append: *append,
+        }),
+        _ => redirect.clone(),
+    }
+}
+
+/// Creates the command to run a target in another process,
+/// giving it `run_args` before its name
+pub fn target_command(target: &str, run_args: &[String]) -> Command {
+    let mut cmd = Command::new(get_app_binary_path());
+    cmd.arg("run").args(run_args).arg(target);
+    continue_run(&mut cmd);
+    cmd
+}
+
+/// Gives a run-tool process started by this one what it needs to be part of the run
+fn continue_run(cmd: &mut Command) {
+    events::share_with(cmd);
+    history::share_with(cmd);
+    cmd.env(helpers::LOG_ENV_VAR, log::max_level().as_str());
+    if helpers::color_disabled() {
+        cmd.env(helpers::NO_COLOR_ENV_VAR, "1");
+    }
+}
+
+/// Opens where a process's input comes from,
+/// starting the target it is piped from when there is one
+fn input_stdio(redirect: &Redirect, piped_targets: &mut Vec<PipedTarget>) -> AppErrorResult<Stdio> {
+    Ok(match redirect {
+        Redirect::Stream(Stream::Inherit) => Stdio::inherit(),
+        Redirect::Stream(Stream::Null) => Stdio::null(),
+        Redirect::File { file, .. } => File::open(file)
+            .map_err(|err| {
+                AppError::file("open input file", Path::new(file), err, exitcode::NOINPUT)
             })?
-            .code()
-            .unwrap_or_default())
+            .into(),
+        Redirect::Target { target } => {
+            let mut piped_target = PipedTarget::spawn(
+                target_command(target, &[]).stdout(Stdio::piped()),
+                target,
+                true,
+            )?;
+            let stdout = piped_target.child.stdout.take().expect("stdout is piped");
+            piped_targets.push(piped_target);
+            stdout.into()
+        }
+    })
+}
+
+/// Opens where a process's output goes to,
+/// starting the target it is piped to when there is one
+fn output_stdio(
+    redirect: &Redirect,
+    piped_targets: &mut Vec<PipedTarget>,
+) -> AppErrorResult<Stdio> {
+    Ok(match redirect {
+        Redirect::Stream(Stream::Inherit) => Stdio::inherit(),
+        Redirect::Stream(Stream::Null) => Stdio::null(),
+        Redirect::File { file, append } => {
+            if let Some(parent) = Path::new(file).parent().filter(|p| *p != Path::new("")) {
+                let _ = fs::create_dir_all(parent);
+            }
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(*append)
+                .truncate(!append)
+                .open(file)
+                .map_err(|err| {
+                    AppError::file(
+                        "open output file",
+                        Path::new(file),
+                        err,
+                        exitcode::CANTCREAT,
+                    )
+                })?
+                .into()
+        }
+        Redirect::Target { target } => {
+            let mut piped_target = PipedTarget::spawn(
+                target_command(target, &[]).stdin(Stdio::piped()),
+                target,
+                false,
+            )?;
+            let stdin = piped_target.child.stdin.take().expect("stdin is piped");
+            piped_targets.push(piped_target);
+            stdin.into()
+        }
+    })
+}
+
+impl ProcessRunner {
+    /// Creates a runner of run-tool itself, to run targets in another process
+    pub fn nested(args: Vec<String>) -> Self {
+        Self {
+            program: get_app_binary_path().to_str().unwrap().to_owned(),
+            args,
+            vars: Default::default(),
+            cwd: None,
+            script_file: None,
+            timeout: None,
+            log_file: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            nested: true,
+        }
     }
 
-    /// Same as `run_interactive()` however will allow the process to be canceled
-    pub fn run_interactive_cancelable(&self, cancel: &AtomicBool) -> AppErrorResult<ExitCode> {
+    /// Creates a runner from the configuration with the parameters filled in,
+    /// appending any extra arguments
+    pub fn from_exec(
+        exec: &ExecConfig,
+        default_shell: Shell,
+        params: &EnvVars,
+        extra_args: &[String],
+    ) -> AppErrorResult<Self> {
+        Self::resolve(exec, default_shell, params, extra_args, true)
+    }
+
+    /// Same as `from_exec()` however a script is not written,
+    /// instead `<script>` is given in place of its path
+    pub fn from_exec_unwritten(
+        exec: &ExecConfig,
+        default_shell: Shell,
+        params: &EnvVars,
+        extra_args: &[String],
+    ) -> AppErrorResult<Self> {
+        Self::resolve(exec, default_shell, params, extra_args, false)
+    }
+
+    fn resolve(
+        exec: &ExecConfig,
+        default_shell: Shell,
+        params: &EnvVars,
+        extra_args: &[String],
+        write_script: bool,
+    ) -> AppErrorResult<Self> {
+        let (program, args) = exec.command.program_args(default_shell);
+        let program = interpolate(&program, params);
+        let mut args: Vec<String> = args.iter().map(|arg| interpolate(arg, params)).collect();
+        let script_file = match &exec.command {
+            ExecCommand::Script { script, .. } if write_script => {
+                let script_file = ScriptFile::new(&interpolate(script, params), &program)?;
+                args.push(script_file.0.to_string_lossy().into_owned());
+                Some(script_file)
+            }
+            ExecCommand::Script { .. } => {
+                args.push(String::from("<script>"));
+                None
+            }
+            _ => None,
+        };
+        args.extend(extra_args.iter().cloned());
+        Ok(Self {
+            program,
+            args,
+            vars: exec
+                .all_vars()
+                .map_err(|err| {
+                    AppError::new(
+                        format!("failed to parse environment files: '{}'", err),
+                        exitcode::DATAERR,
+                    )
+                })?
+                .into_iter()
+                .map(|(k, v)| (k, interpolate(&v, params)))
+                .collect(),
+            cwd: exec.cwd.as_ref().map(|cwd| interpolate(cwd, params)),
+            script_file,
+            timeout: exec.timeout.map(Into::into),
+            log_file: None,
+            stdin: interpolate_redirect(&exec.stdin, params),
+            stdout: interpolate_redirect(&exec.stdout, params),
+            stderr: interpolate_redirect(&exec.stderr, params),
+            nested: false,
+        })
+    }
+
+    /// Overrides the configured timeout, when one is given
+    pub fn with_timeout(mut self, timeout: Option<Seconds>) -> Self {
+        if let Some(timeout) = timeout {
+            self.timeout = Some(timeout.into());
+        }
+        self
+    }
+
+    fn command(&self) -> Command {
+        if let Some(script_file) = &self.script_file {
+            log::debug!("running script written to '{}'", script_file.0.display());
+        }
         let mut cmd = Command::new(&self.program);
         cmd.envs(&self.vars).args(&self.args);
         if let Some(cwd) = &self.cwd {
             cmd.current_dir(cwd);
         }
-        let mut child_process = cmd.spawn().map_err(|err| AppError {
-            msg: format!("{}", err),
-            exitcode: exitcode::SOFTWARE,
-        })?;
+        if self.nested {
+            continue_run(&mut cmd);
+        }
+        cmd
+    }
+
+    /// Starts the process in the background with stdout and stderr captured,
+    /// on unix it will be in its own process group
+    pub fn spawn_captured(&self) -> AppErrorResult<Child> {
+        let mut cmd = self.command();
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        cmd.spawn()
+            .map_err(|err| AppError::spawn(&self.program, self.cwd.as_deref(), err))
+    }
+
+    /// Starts the process attached to the terminal, when it may need stopping
+    /// on unix it will be in its own process group so all of it can be stopped.
+    /// Only the foreground process group can read from a terminal,
+    /// so when its input is the terminal it is given it whilst running.
+    /// Output is written to the log file when there is one, by the returned threads
+    fn spawn_interactive(&self, stoppable: bool) -> AppErrorResult<RunningProcess> {
+        let mut cmd = self.command();
+        if self.log_file.is_some() {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        // redirects take the place of the log file
+        let mut piped_targets = Vec::new();
+        if let Some(redirect) = &self.stdin {
+            cmd.stdin(input_stdio(redirect, &mut piped_targets)?);
+        }
+        if let Some(redirect) = &self.stdout {
+            cmd.stdout(output_stdio(redirect, &mut piped_targets)?);
+        }
+        if let Some(redirect) = &self.stderr {
+            cmd.stderr(output_stdio(redirect, &mut piped_targets)?);
+        }
+        let own_group = cfg!(unix) && (stoppable || self.timeout.is_some());
+        let foreground = own_group && self.stdin.is_none() && in_foreground();
+        #[cfg(unix)]
+        if own_group {
+            use std::os::unix::process::CommandExt;
+            // interrupts no longer reach the process group, so must be forwarded
+            catch_interrupts();
+            cmd.process_group(0);
+        }
+        #[cfg(unix)]
+        if foreground {
+            use std::os::unix::process::CommandExt;
+            // SAFETY: runs in the child before exec, only calling functions which are async-signal-safe
+            unsafe {
+                cmd.pre_exec(take_terminal);
+            }
+        }
+        let mut child = cmd
+            .spawn()
+            .map_err(|err| AppError::spawn(&self.program, self.cwd.as_deref(), err))?;
+        let mut output_threads = Vec::new();
+        if let Some(log_file) = &self.log_file {
+            if let Some(stdout) = child.stdout.take() {
+                output_threads.push(forward_to_log(stdout, log_file.clone(), false));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                output_threads.push(forward_to_log(stderr, log_file.clone(), true));
+            }
+        }
+        Ok(RunningProcess {
+            child,
+            own_group,
+            foreground,
+            output_threads,
+            piped_targets,
+        })
+    }
+
+    /// Waits for the process to exit, stopping it once the timeout is reached.
+    /// Whilst running `on_poll` is called, which can ask for it to be stopped,
+    /// giving a successful status once it has
+    fn wait(
+        &self,
+        RunningProcess {
+            child: mut child_process,
+            own_group,
+            foreground,
+            output_threads,
+            piped_targets,
+        }: RunningProcess,
+        mut on_poll: impl FnMut(&mut Child) -> AppErrorResult<bool>,
+    ) -> AppErrorResult<ExitCode> {
+        let started = Instant::now();
+        let mut timed_out = false;
+        let mut canceled = false;
+        let mut stopping = Stopping::default();
         loop {
             match child_process.try_wait() {
-                Ok(Some(status)) => return Ok(status.code().unwrap_or_default()),
-                Ok(None) => {
-                    if cancel.load(Ordering::Relaxed) {
-                        child_process.kill().map_err(|_| AppError {
-                            msg: String::from("process could not be killed"),
-                            exitcode: exitcode::OSERR,
-                        })?;
+                Ok(Some(status)) => {
+                    #[cfg(unix)]
+                    if foreground {
+                        use std::os::unix::process::ExitStatusExt;
+                        if let Err(err) = take_terminal() {
+                            log::warn!("failed to take back the terminal: '{}'", err);
+                        }
+                        // the interrupt only reached the process, as it had the terminal
+                        if status.signal() == Some(libc::SIGINT) {
+                            INTERRUPTED.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    for output_thread in output_threads {
+                        let _ = output_thread.join();
+                    }
+                    let mut piped_status = exitcode::OK;
+                    if canceled || timed_out || interrupted() {
+                        // stopped early, so are the targets connected to it
+                        drop(piped_targets);
+                    } else {
+                        for mut piped_target in piped_targets {
+                            let code = piped_target.wait();
+                            if exitcode::is_error(code) {
+                                log::error!(
+                                    "piped target '{}' exited with status {}",
+                                    piped_target.name,
+                                    code
+                                );
+                                piped_status = code;
+                            }
+                        }
+                    }
+                    exit_if_interrupted();
+                    if canceled {
                         return Ok(exitcode::OK);
                     }
-                    ()
+                    if timed_out {
+                        log::error!(
+                            "'{}' timed out after {:.1}s",
+                            self.program,
+                            started.elapsed().as_secs_f64()
+                        );
+                        return Ok(TIMED_OUT_EXIT_CODE);
+                    }
+                    // like `pipefail`, a failing piped target fails the process
+                    return Ok(match status.code().unwrap_or_default() {
+                        exitcode::OK => piped_status,
+                        code => code,
+                    });
                 }
-
-                Err(_) => {
-                    return Err(AppError {
-                        msg: String::from("failure"),
-                        exitcode: exitcode::OSERR,
-                    })
+                Ok(None) => {
+                    #[cfg(unix)]
+                    if foreground {
+                        follow_terminal_stop(&child_process);
+                    }
+                    let cancel = on_poll(&mut child_process)?;
+                    if !stopping.started() {
+                        canceled = cancel;
+                        timed_out = !cancel && self.timeout.is_some_and(|t| started.elapsed() > t);
+                    }
+                    // otherwise the interrupt already reached it
+                    let interrupt = interrupted() && own_group;
+                    stopping.poll(canceled || timed_out || interrupt, |force| {
+                        stop_process(&mut child_process, own_group, force)
+                    });
                 }
+                Err(err) => return Err(AppError::process("wait for", &self.program, err)),
             }
             sleep(Duration::from_millis(1));
         }
     }
+
+    /// Runs the process interactively allowing user to see stdout and use stdin
+    pub fn run_interactive(&self) -> AppErrorResult<ExitCode> {
+        self.wait(self.spawn_interactive(false)?, |_| Ok(false))
+    }
+
+    /// Same as `run_interactive()` however will allow the process to be canceled
+    pub fn run_interactive_cancelable(&self, cancel: &AtomicBool) -> AppErrorResult<ExitCode> {
+        self.wait(self.spawn_interactive(true)?, |_| {
+            Ok(cancel.load(Ordering::Relaxed))
+        })
+    }
+
+    /// Same as `run_interactive()` however will send a signal to the process
+    /// each time `trigger` is set, leaving it running.
+    /// Before signalling `before_signal` is called, which can decide not to
+    #[cfg(unix)]
+    pub fn run_interactive_signalable(
+        &self,
+        trigger: &AtomicBool,
+        signal: Signal,
+        mut before_signal: impl FnMut() -> bool,
+    ) -> AppErrorResult<ExitCode> {
+        self.wait(self.spawn_interactive(false)?, |child_process| {
+            if trigger.swap(false, Ordering::Relaxed) && before_signal() {
+                log::debug!("sending {:?} to process {}", signal, child_process.id());
+                // SAFETY: the pid belongs to a child that has not yet been reaped
+                if unsafe { libc::kill(child_process.id() as i32, signal.as_raw()) } != 0 {
+                    return Err(AppError::process(
+                        "signal",
+                        &self.program,
+                        std::io::Error::last_os_error(),
+                    ));
+                }
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// Catches interrupts from the user instead of exiting straight away,
+/// so processes in their own process group can be stopped first
+pub fn catch_interrupts() {
+    INTERRUPT_HANDLER.call_once(|| {
+        if let Err(err) = ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed)) {
+            log::warn!("failed to handle interrupts: '{}'", err);
+        }
+    });
+}
+
+/// Whether the user has interrupted, once interrupts are caught
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Exits with the status of an interrupt once the user has interrupted,
+/// as interrupts are only caught to stop processes first
+pub fn exit_if_interrupted() {
+    if interrupted() {
+        history::finish(INTERRUPTED_EXIT_CODE);
+        exit(INTERRUPTED_EXIT_CODE);
+    }
+}
+
+/// The signal asking a process to stop, or killing it when `force` is set
+#[cfg(unix)]
+fn stop_signal(force: bool) -> Signal {
+    match (force, interrupted()) {
+        (true, _) => Signal::Sigkill,
+        (false, true) => Signal::Sigint,
+        (false, false) => Signal::Sigterm,
+    }
+}
+
+/// Asks the child's process group to stop, or kills it when `force` is set,
+/// the child must have been started in its own process group
+pub fn stop_process_group(child: &mut Child, force: bool) {
+    #[cfg(unix)]
+    signal_process_group(child, stop_signal(force));
+    #[cfg(not(unix))]
+    {
+        let _ = force;
+        let _ = child.kill();
+    }
+}
+
+/// Same as `stop_process_group()` however when the child is not in its own
+/// process group only the child is stopped
+pub fn stop_process(child: &mut Child, own_group: bool, force: bool) {
+    #[cfg(unix)]
+    if !own_group {
+        let signal = stop_signal(force);
+        log::debug!("sending {:?} to process {}", signal, child.id());
+        // SAFETY: the pid belongs to a child that has not yet been reaped
+        unsafe { libc::kill(child.id() as i32, signal.as_raw()) };
+        return;
+    }
+    #[cfg(not(unix))]
+    let _ = own_group;
+    stop_process_group(child, force);
+}
+
+/// Sends a signal to every process in the child's process group,
+/// the child must have been started with `ProcessRunner::spawn_captured()`.
+/// Once the child has exited, this still reaches what it started
+#[cfg(unix)]
+pub fn signal_process_group(child: &Child, signal: Signal) {
+    log::debug!("sending {:?} to process group {}", signal, child.id());
+    // SAFETY: only sends a signal, the process group id can't be reused whilst the group exists
+    unsafe { libc::killpg(child.id() as i32, signal.as_raw()) };
+}
+
+/// Whether this process is in the foreground process group of the terminal its input is from
+fn in_foreground() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: only queries the process groups, with no memory passed in
+        io::stdin().is_terminal()
+            && unsafe { libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp() }
+    }
+    #[cfg(not(unix))]
+    false
+}
+
+/// Makes the process group the foreground process group of the terminal input is from.
+/// `SIGTTOU` is blocked whilst doing so, as otherwise doing so from
+/// a background process group stops the calling process.
+/// Only calls functions which are async-signal-safe, so can be run before exec
+#[cfg(unix)]
+fn give_terminal(process_group: libc::pid_t) -> io::Result<()> {
+    // SAFETY: the signal sets are initialised by sigemptyset before being used
+    unsafe {
+        let mut block = std::mem::zeroed();
+        let mut previous = std::mem::zeroed();
+        libc::sigemptyset(&mut block);
+        libc::sigaddset(&mut block, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &block, &mut previous);
+        let result = libc::tcsetpgrp(libc::STDIN_FILENO, process_group);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &previous, std::ptr::null_mut());
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Makes the calling process's group the foreground process group of the terminal
+#[cfg(unix)]
+fn take_terminal() -> io::Result<()> {
+    // SAFETY: getpgrp has no preconditions and is async-signal-safe
+    give_terminal(unsafe { libc::getpgrp() })
+}
+
+/// When a child given the terminal has been stopped from it, such as by ctrl-z,
+/// stops this process too so what started it gets the terminal back.
+/// Once continued the child is given the terminal again and continued
+#[cfg(unix)]
+fn follow_terminal_stop(child: &Child) {
+    // SAFETY: the info is zeroed before being filled in, and the child has not yet been reaped
+    let stopped = unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        // the child is left waitable, so it is still reaped with its status
+        libc::waitid(
+            libc::P_PID,
+            child.id(),
+            &mut info,
+            libc::WSTOPPED | libc::WNOHANG | libc::WNOWAIT,
+        ) == 0
+            && info.si_pid() != 0
+    };
+    if !stopped {
+        return;
+    }
+    log::debug!("process {} was stopped, stopping too", child.id());
+    let _ = take_terminal();
+    // SAFETY: only signals this process, then the child's process group that has not yet been reaped
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+        // continued, so carries on running the child
+        let _ = give_terminal(child.id() as libc::pid_t);
+        libc::killpg(child.id() as libc::pid_t, libc::SIGCONT);
+    }
 }
@@ -0,0 +1,105 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{Mutex, MutexGuard, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::errors::{AppError, AppErrorResult};
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static SPANS: Mutex<Vec<Span>> = Mutex::new(Vec::new());
+
+/// A part of a run and how long it took
+struct Span {
+    part: &'static str,
+    name: String,
+    start: Duration,
+    duration: Duration,
+}
+
+/// An event in the Chrome trace event format, with times in microseconds
+#[derive(Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: &'a str,
+    ph: &'static str,
+    ts: u128,
+    dur: u128,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<TraceEvent<'a>>,
+    display_time_unit: &'static str,
+}
+
+/// Times running `f` as a part of the run e.g. a check or hook
+pub fn record<T>(part: &'static str, name: &str, f: impl FnOnce() -> T) -> T {
+    let started = *STARTED.get_or_init(Instant::now);
+    let start = started.elapsed();
+    let result = f();
+    SPANS.lock().unwrap().push(Span {
+        part,
+        name: name.to_owned(),
+        start,
+        duration: started.elapsed() - start,
+    });
+    result
+}
+
+/// Gets the recorded spans in the order they started,
+/// with parts started at the same time ordered by how long they took
+fn sorted_spans() -> MutexGuard<'static, Vec<Span>> {
+    let mut spans = SPANS.lock().unwrap();
+    spans.sort_by(|a, b| a.start.cmp(&b.start).then(b.duration.cmp(&a.duration)));
+    spans
+}
+
+/// Prints how long each part of the run took
+pub fn print_report() {
+    let spans = sorted_spans();
+    let part_width = spans.iter().map(|s| s.part.len()).fold(4, usize::max);
+    let name_width = spans.iter().map(|s| s.name.len()).fold(4, usize::max);
+    eprintln!(
+        "{:<part_width$}  {:<name_width$}  {:>9}",
+        "part", "name", "duration"
+    );
+    for span in spans.iter() {
+        eprintln!(
+            "{:<part_width$}  {:<name_width$}  {:>8.3}s",
+            span.part,
+            span.name,
+            span.duration.as_secs_f64()
+        );
+    }
+}
+
+/// Writes the recorded spans as a Chrome trace, which can be opened with `chrome://tracing`
+pub fn write_trace(path: &Path) -> AppErrorResult<()> {
+    let spans = sorted_spans();
+    let trace = Trace {
+        trace_events: spans
+            .iter()
+            .map(|span| TraceEvent {
+                name: &span.name,
+                cat: span.part,
+                ph: "X",
+                ts: span.start.as_micros(),
+                dur: span.duration.as_micros(),
+                pid: std::process::id(),
+                tid: 1,
+            })
+            .collect(),
+        display_time_unit: "ms",
+    };
+    let contents = serde_json::to_string(&trace)
+        .map_err(|err| AppError::new(err.to_string(), exitcode::SOFTWARE))?;
+    fs::write(path, contents)
+        .map_err(|err| AppError::file("write trace to", path, err, exitcode::CANTCREAT))
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use exitcode::ExitCode;
+
+use notify_debouncer_mini::{
+    new_debouncer_opt,
+    notify::{self, PollWatcher, RecommendedWatcher, Watcher},
+    DebounceEventHandler, Debouncer,
+};
+
+use crate::config::{Config, TargetConfig};
+
+/// A part of a target's run, ordered by when it is executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    BeforeHook(usize),
+    Exec,
+    AfterHook(usize),
+}
+
+/// The paths each stage of a target is watching
+#[derive(Clone)]
+pub struct StageWatches {
+    watches: Vec<(Stage, Vec<PathBuf>)>,
+}
+
+/// Makes path absolute so it can be compared against paths given by the watcher
+fn absolute_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => path.to_owned(),
+    })
+}
+
+impl StageWatches {
+    /// Collects the watched paths for the target and each of its hooks
+    pub fn new(config: &Config, target_config: &TargetConfig) -> Self {
+        let hook_paths = |name: &String| -> Vec<PathBuf> {
+            config
+                .targets
+                .get(name)
+                .map(|hook| hook.watch.paths.iter().map(|p| absolute_path(p)).collect())
+                .unwrap_or_default()
+        };
+        let mut watches = Vec::new();
+        for (i, name) in target_config.before_hooks.iter().enumerate() {
+            watches.push((Stage::BeforeHook(i), hook_paths(name)));
+        }
+        watches.push((
+            Stage::Exec,
+            target_config
+                .watch
+                .paths
+                .iter()
+                .map(|p| absolute_path(p))
+                .collect(),
+        ));
+        for (i, name) in target_config.after_hooks.iter().enumerate() {
+            watches.push((Stage::AfterHook(i), hook_paths(name)));
+        }
+        Self { watches }
+    }
+
+    /// Every stage of the target
+    pub fn all(&self) -> BTreeSet<Stage> {
+        self.watches.iter().map(|(stage, _)| *stage).collect()
+    }
+
+    /// Every unique path that needs to be watched
+    pub fn paths(&self) -> BTreeSet<&PathBuf> {
+        self.watches
+            .iter()
+            .flat_map(|(_, paths)| paths.iter())
+            .collect()
+    }
+
+    /// Stages that need to run again due to the changed paths,
+    /// including any stages depending on them
+    pub fn affected(&self, changed: &[PathBuf]) -> BTreeSet<Stage> {
+        let mut affected: BTreeSet<Stage> = self
+            .watches
+            .iter()
+            .filter(|(_, paths)| {
+                paths
+                    .iter()
+                    .any(|path| changed.iter().any(|c| c.starts_with(path)))
+            })
+            .map(|(stage, _)| *stage)
+            .collect();
+        // the program depends on all before hooks and all after hooks depend on the program
+        if affected.first().is_some_and(|stage| *stage <= Stage::Exec) {
+            affected.extend(self.all().into_iter().filter(|stage| *stage >= Stage::Exec));
+        }
+        affected
+    }
+}
+
+/// Clears the terminal screen and scrollback, when attached to one
+pub fn clear_screen() {
+    let mut stdout = io::stdout();
+    if stdout.is_terminal() {
+        let _ = stdout.write_all(b"\x1B[2J\x1B[3J\x1B[H");
+        let _ = stdout.flush();
+    }
+}
+
+/// Creates the line shown when restarting due to changes, including how the program's
+/// previous run ended when there was one, where a status of `None` means it was stopped
+pub fn restart_banner(
+    target_name: &str,
+    changed: &[PathBuf],
+    previous_run: Option<(Option<ExitCode>, Duration)>,
+) -> String {
+    let cwd = env::current_dir().unwrap_or_default();
+    let changed = match changed {
+        [] => String::from("nothing"),
+        [path] => path
+            .strip_prefix(&cwd)
+            .unwrap_or(path)
+            .display()
+            .to_string(),
+        [path, others @ ..] => format!(
+            "{} and {} other(s)",
+            path.strip_prefix(&cwd).unwrap_or(path).display(),
+            others.len()
+        ),
+    };
+    let previous = match previous_run {
+        Some((Some(status), elapsed)) => format!(
+            " (previous run exited with status {} after {:.1}s)",
+            status,
+            elapsed.as_secs_f64()
+        ),
+        Some((None, elapsed)) => format!(
+            " (previous run was stopped after {:.1}s)",
+            elapsed.as_secs_f64()
+        ),
+        None => String::new(),
+    };
+    format!(
+        "[{}] restarting {}: {} changed{}",
+        chrono::Local::now().format("%H:%M:%S"),
+        target_name,
+        changed,
+        previous,
+    )
+}
+
+/// A running file watcher, stopping when dropped
+pub enum WatcherGuard {
+    Native(Debouncer<RecommendedWatcher>),
+    Polling(Debouncer<PollWatcher>),
+}
+
+impl WatcherGuard {
+    /// Creates a watcher, using polling when an interval is given
+    pub fn new<F: DebounceEventHandler>(
+        debounce: Duration,
+        poll_interval: Option<Duration>,
+        event_handler: F,
+    ) -> Result<Self, notify::Error> {
+        let config = notify_debouncer_mini::Config::default().with_timeout(debounce);
+        Ok(match poll_interval {
+            None => Self::Native(new_debouncer_opt(config, event_handler)?),
+            Some(interval) => Self::Polling(new_debouncer_opt(
+                config.with_notify_config(notify::Config::default().with_poll_interval(interval)),
+                event_handler,
+            )?),
+        })
+    }
+
+    pub fn is_polling(&self) -> bool {
+        matches!(self, Self::Polling(_))
+    }
+
+    pub fn watcher(&mut self) -> &mut dyn Watcher {
+        match self {
+            Self::Native(v) => v.watcher(),
+            Self::Polling(v) => v.watcher(),
+        }
+    }
+}
+
+/// Gets the name of the filesystem at path, when it is known to not deliver change events
+#[cfg(target_os = "linux")]
+pub fn unsupported_filesystem(path: &Path) -> Option<&'static str> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    const NFS: u32 = 0x6969;
+    const SMB: u32 = 0x517B;
+    const SMB2: u32 = 0xFE534D42;
+    const CIFS: u32 = 0xFF534D42;
+    const FUSE: u32 = 0x65735546;
+    const V9FS: u32 = 0x01021997;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: path is a valid c string and stat is only read when the call succeeds
+    if unsafe { libc::statfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statfs succeeded, so has filled in stat
+    match unsafe { stat.assume_init() }.f_type as u32 {
+        NFS => Some("nfs"),
+        SMB | SMB2 | CIFS => Some("smb"),
+        FUSE => Some("fuse"),
+        V9FS => Some("9p"),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unsupported_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watches() -> StageWatches {
+        let config: Config = serde_yml::from_str(
+            r#"
+targets:
+  gen:
+    program: "true"
+    watch:
+      paths: [/project/schema]
+  lint:
+    program: "true"
+  docs:
+    program: "true"
+    watch:
+      paths: [/project/docs]
+  serve:
+    program: "true"
+    before_hooks: [gen, lint]
+    after_hooks: [docs]
+    watch:
+      paths: [/project/src]
+"#,
+        )
+        .unwrap();
+        StageWatches::new(&config, &config.targets["serve"])
+    }
+
+    fn affected(changed: &str) -> Vec<Stage> {
+        watches()
+            .affected(&[PathBuf::from(changed)])
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn before_hook_change_runs_it_and_everything_after() {
+        assert_eq!(
+            affected("/project/schema/api.json"),
+            [Stage::BeforeHook(0), Stage::Exec, Stage::AfterHook(0)]
+        );
+    }
+
+    #[test]
+    fn program_change_runs_it_and_after_hooks() {
+        assert_eq!(
+            affected("/project/src/main.rs"),
+            [Stage::Exec, Stage::AfterHook(0)]
+        );
+    }
+
+    #[test]
+    fn after_hook_change_only_runs_it() {
+        assert_eq!(affected("/project/docs/index.md"), [Stage::AfterHook(0)]);
+    }
+
+    #[test]
+    fn unwatched_change_runs_nothing() {
+        assert!(affected("/project/README.md").is_empty());
+        assert!(affected("/project/srcs/main.rs").is_empty());
+    }
+}